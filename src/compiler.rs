@@ -17,7 +17,15 @@ use inkwell::values::{FunctionValue, PointerValue};
 use inkwell::AddressSpace;
 use inkwell::OptimizationLevel;
 
+/// The value passed between compiled code and the runtime. When `error` is
+/// not `0` the `Wrap` is an error and `json` points to the error value,
+/// usually a message string but `error(v)` can raise any JSON value.
+///
+/// `json` never owns its value: it points into the input, into a literal
+/// owned by the `Script` or into the runtime arena that owns every value
+/// created while processing the current input (see `std_lib::alloc`).
 #[derive(Debug)]
+#[repr(C)]
 pub struct Wrap {
     pub error: u64,
     pub json: *const Value,
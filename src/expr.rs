@@ -9,6 +9,7 @@ pub enum Expr {
     Sub(SubExpr),
     Mul(MulExpr),
     Div(DivExpr),
+    Mod(ModExpr),
     Var(String),
     Let(String, Box<Expr>),
     Paren(Box<Expr>),
@@ -23,6 +24,7 @@ impl Display for Expr {
             Sub(ref e) => write!(format, "{} - {}", e.left, e.right),
             Mul(ref e) => write!(format, "{} * {}", e.left, e.right),
             Div(ref e) => write!(format, "{} / {}", e.left, e.right),
+            Mod(ref e) => write!(format, "{} % {}", e.left, e.right),
             Var(ref v) => write!(format, "{}", v),
             Let(ref v, ref right) => write!(format, "let {} = {}", v, right),
             Paren(ref expr) => write!(format, "({})", expr),
@@ -39,6 +41,7 @@ impl Debug for Expr {
             Sub(ref e) => write!(format, "({:?} - {:?})", e.left, e.right),
             Mul(ref e) => write!(format, "({:?} * {:?})", e.left, e.right),
             Div(ref e) => write!(format, "({:?} / {:?})", e.left, e.right),
+            Mod(ref e) => write!(format, "({:?} % {:?})", e.left, e.right),
             Var(ref v) => write!(format, "{:?}", v),
             Let(ref v, ref right) => write!(format, "let {:?} = {:?}", v, right),
             Paren(ref expr) => write!(format, "[{:?}]", expr),
@@ -97,6 +100,19 @@ impl Compile<IntValue, MathCompiler> for DivExpr {
         Ok(compiler.builder().build_int_signed_div(l, r, "add"))
     }
 }
+
+pub struct ModExpr {
+    pub left: Box<Expr>,
+    pub right: Box<Expr>,
+}
+
+impl Compile<IntValue, MathCompiler> for ModExpr {
+    fn compile(&self, compiler: &mut MathCompiler) -> Result<IntValue, CompilerError> {
+        let l = self.left.compile(compiler)?;
+        let r = self.right.compile(compiler)?;
+        Ok(compiler.builder().build_int_signed_rem(l, r, "rem"))
+    }
+}
 impl Compile<IntValue, MathCompiler> for Expr {
     fn compile(&self, compiler: &mut MathCompiler) -> Result<IntValue, CompilerError> {
         let i64_type = compiler.context().i64_type();
@@ -110,6 +126,7 @@ impl Compile<IntValue, MathCompiler> for Expr {
             Expr::Sub(e) => e.compile(compiler),
             Expr::Mul(e) => e.compile(compiler),
             Expr::Div(e) => e.compile(compiler),
+            Expr::Mod(e) => e.compile(compiler),
             Expr::Paren(e) => e.compile(compiler),
             Expr::Var(ref name) => match compiler.variables.get(name.as_str()) {
                 Some(var) => Ok(compiler
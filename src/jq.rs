@@ -1,7 +1,14 @@
 use crate::compiler::{Compile, Compiler, CompilerError, Wrap};
+use crate::parser::Oper;
+use crate::std_lib::{
+    compile_regex, Prototype, Regex, FORMATS, MATH_1, MATH_2, MATH_3, REGEX_CAPTURE,
+    REGEX_GLOBAL, REGEX_MATCH, REGEX_SCAN, REGEX_SPLIT, REGEX_TEST,
+};
 use crate::STDLIB;
 
-use std::collections::HashMap;
+use simd_json::OwnedValue as Value;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::rc::Rc;
 
 use inkwell::basic_block::BasicBlock;
 use inkwell::builder::Builder;
@@ -9,21 +16,260 @@ use inkwell::context::Context;
 use inkwell::execution_engine::{ExecutionEngine, JitFunction};
 use inkwell::module::Module;
 use inkwell::types::{BasicTypeEnum, StructType};
-use inkwell::values::{FunctionValue, PointerValue, StructValue};
+use inkwell::values::{
+    BasicValueEnum, FloatValue, FunctionValue, IntValue, PointerValue, StructValue,
+};
 use inkwell::AddressSpace;
+use inkwell::IntPredicate;
 use inkwell::OptimizationLevel;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Path {
     Root,
     Key(String),
-    Idx(usize),
+    /// `.[n]`, negative indices count from the end.
+    Idx(i64),
+    /// `.[from:to]` on arrays and strings, missing bounds are open.
+    Slice(Option<i64>, Option<i64>),
+    /// `.[]`, every element of an array or value of an object.
+    Iter,
+}
+
+/// Comparison operators, all of them use jq's total order of JSON values.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl CmpOp {
+    /// The predicate that tests the result of `jq_compare` against `0`.
+    fn predicate(self) -> IntPredicate {
+        match self {
+            CmpOp::Eq => IntPredicate::EQ,
+            CmpOp::Ne => IntPredicate::NE,
+            CmpOp::Lt => IntPredicate::SLT,
+            CmpOp::Le => IntPredicate::SLE,
+            CmpOp::Gt => IntPredicate::SGT,
+            CmpOp::Ge => IntPredicate::SGE,
+        }
+    }
+}
+
+/// A piece of an interpolated string.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StringPart {
+    Lit(String),
+    /// `\(f)`
+    Interp(Filter),
+}
+
+/// Assignment operators, the left hand side is a path expression.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AssignOp {
+    /// `=`, sets the paths to each output of the right hand side, which
+    /// runs on `.`.
+    Set,
+    /// `|=`, runs the right hand side on the value at each path and
+    /// deletes the paths it yields nothing for.
+    Update,
+    /// `+=`, `-=`, ..., combines the value at each path with the outputs of
+    /// the right hand side.
+    Arith(Oper),
+    /// `//=`, sets the paths whose value is `false` or `null`.
+    Alt,
+}
+
+/// A jq filter, the AST `parser::filter` produces and `Script` compiles.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    /// A path chain like `.a.b[0]`, always starting at `Path::Root`.
+    Path(Vec<Path>),
+    /// `lhs | rhs`, feeds the output of `lhs` into `rhs`.
+    Pipe(Box<Filter>, Box<Filter>),
+    /// `lhs, rhs`, all outputs of `lhs` followed by all outputs of `rhs`.
+    Comma(Box<Filter>, Box<Filter>),
+    /// `try body catch handler`, `body?` is `try body` without a handler.
+    Try(Box<Filter>, Option<Box<Filter>>),
+    /// `lhs op rhs` for an arithmetic operator.
+    Arith(Oper, Box<Filter>, Box<Filter>),
+    /// `lhs op rhs` for a comparison operator.
+    Cmp(CmpOp, Box<Filter>, Box<Filter>),
+    /// `lhs // rhs`, the outputs of `lhs` that are neither `false` nor `null`
+    /// or, if there are none, the outputs of `rhs`. Errors in `lhs` count as
+    /// no output.
+    Alternative(Box<Filter>, Box<Filter>),
+    /// `lhs op= rhs`, updates the values at the paths `lhs` produces.
+    Assign(AssignOp, Box<Filter>, Box<Filter>),
+    /// `lhs and rhs`, short circuits when `lhs` is falsy.
+    And(Box<Filter>, Box<Filter>),
+    /// `lhs or rhs`, short circuits when `lhs` is truthy.
+    Or(Box<Filter>, Box<Filter>),
+    /// `if cond then a else b end`, `elif` chains nest in the else branch
+    /// and a missing else branch passes the input through.
+    If(Box<Filter>, Box<Filter>, Option<Box<Filter>>),
+    /// A JSON literal like `1`, `"a"` or `null`.
+    Literal(Value),
+    /// `[f]`, an array of all outputs of `f`.
+    Array(Option<Box<Filter>>),
+    /// `{k: v, ...}`, one object for every combination of key and value outputs.
+    Object(Vec<(Filter, Filter)>),
+    /// `@name`, formats the input as a string.
+    Format(String),
+    /// `@name "a\(f)b"`, a string for every combination of outputs of the
+    /// interpolated filters, which are formatted with `@name` (`@text` for
+    /// plain strings).
+    Interpolate(String, Vec<StringPart>),
+    /// A variable reference `$name`.
+    Var(String),
+    /// `source as p1 ?// p2 | body`, runs `body` once for every output of
+    /// `source` with the variables of the first pattern that matches it.
+    Bind(Box<Filter>, Vec<Pattern>, Box<Filter>),
+    /// `reduce source as $x (init; update)`, folds the outputs of `source`
+    /// into a state that starts as `init`, the last output of `update` wins.
+    Reduce(Box<Filter>, Vec<Pattern>, Box<Filter>, Box<Filter>),
+    /// `foreach source as $x (init; update; extract)`, like `reduce` but
+    /// emits `extract` of every intermediate state (the state itself when
+    /// there is no `extract`).
+    Foreach(
+        Box<Filter>,
+        Vec<Pattern>,
+        Box<Filter>,
+        Box<Filter>,
+        Option<Box<Filter>>,
+    ),
+    /// `def name(params): body; rest`, `name` is visible in `body` and `rest`.
+    Def(Box<FuncDef>, Box<Filter>),
+    /// A call to a `def`, a filter parameter or a builtin like `error(msg)`.
+    Call(String, Vec<Filter>),
+}
+
+/// `def name(f; g): body;`, the parser turns a `$x` parameter into the filter
+/// parameter `x` whose outputs are bound to `$x` around the body.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FuncDef {
+    pub name: String,
+    pub params: Vec<String>,
+    pub body: Filter,
+}
+
+/// Code together with the scope it was written in, the argument passed for
+/// a filter parameter or a `def`.
+pub struct Closure<T> {
+    pub code: T,
+    pub scope: Scope,
+}
+
+/// What a call refers to when it isn't a builtin.
+#[derive(Clone)]
+pub enum Callable {
+    Def(Rc<Closure<FuncDef>>),
+    Param(Rc<Closure<Filter>>),
+    /// A filter parameter whose closure is only known at runtime, the
+    /// variable with this id holds it (see `Script::build_closure`).
+    Dynamic(usize),
+}
+
+/// The lexical scope of the code being compiled. Variables are known by an
+/// id, the function being compiled keeps their values in `Script::slots`.
+#[derive(Clone, Default)]
+pub struct Scope {
+    pub variables: HashMap<String, usize>,
+    pub functions: HashMap<(String, usize), Callable>,
+}
+
+impl Scope {
+    /// The value of `f` if it is a literal, filter arguments are followed to
+    /// the literal passed for them.
+    fn constant(&self, f: &Filter) -> Option<Value> {
+        match f {
+            Filter::Literal(v) => Some(v.clone()),
+            Filter::Call(name, args) if args.is_empty() => {
+                match self.functions.get(&(name.clone(), 0)) {
+                    Some(Callable::Param(arg)) => arg.scope.constant(&arg.code),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Adds the ids of all variables code in this scope can reach, including
+    /// through the functions and filter arguments it can call. `seen` holds
+    /// the closures already visited, scopes share most of them.
+    fn captures(&self, ids: &mut BTreeSet<usize>, seen: &mut HashSet<usize>) {
+        ids.extend(self.variables.values().cloned());
+        for f in self.functions.values() {
+            let (ptr, scope) = match f {
+                Callable::Def(def) => (Rc::as_ptr(def) as usize, &def.scope),
+                Callable::Param(arg) => (Rc::as_ptr(arg) as usize, &arg.scope),
+                Callable::Dynamic(id) => {
+                    ids.insert(*id);
+                    continue;
+                }
+            };
+            if seen.insert(ptr) {
+                scope.captures(ids, seen);
+            }
+        }
+    }
+}
+
+/// A destructuring pattern on the right hand side of `as`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    /// `$name`, binds the whole value.
+    Var(String),
+    /// `[p0, p1, ...]`, matches element `n` against `pn`.
+    Array(Vec<Pattern>),
+    /// `{key: p, $name, ...}`, each entry has a key filter (run against the
+    /// matched value), a variable bound to `.[key]` and a pattern `.[key]` is
+    /// matched against, `{$name}` is short for `{"name": $name}`.
+    Object(Vec<(Filter, Option<String>, Option<Pattern>)>),
+}
+
+impl Pattern {
+    /// Appends the names of the variables the pattern binds to `names`.
+    fn variables(&self, names: &mut Vec<String>) {
+        match self {
+            Pattern::Var(name) => names.push(name.clone()),
+            Pattern::Array(elems) => elems.iter().for_each(|p| p.variables(names)),
+            Pattern::Object(entries) => {
+                for (_, var, pattern) in entries {
+                    names.extend(var.iter().cloned());
+                    if let Some(p) = pattern {
+                        p.variables(names);
+                    }
+                }
+            }
+        }
+    }
 }
 
 pub trait JQCompile<Ret, Comp: Compiler> {
     fn compile(&self, compiler: &Comp, val: StructValue) -> Result<Ret, CompilerError>;
 }
 
+/// Continuation a generator calls once for every value it produces, it
+/// emits the code that consumes that value.
+pub type Cont<'a, Comp> = dyn FnMut(&mut Comp, StructValue) -> Result<(), CompilerError> + 'a;
+
+/// Filters are compiled as generators: instead of returning a single value
+/// the generated code runs the continuation `k` for every output and falls
+/// through once the generator is exhausted.
+pub trait JQGenerate<Comp: Compiler> {
+    fn generate(
+        &self,
+        compiler: &mut Comp,
+        val: StructValue,
+        k: &mut Cont<Comp>,
+    ) -> Result<(), CompilerError>;
+}
+
 impl JQCompile<StructValue, Script> for Path {
     fn compile(&self, compiler: &Script, val: StructValue) -> Result<StructValue, CompilerError> {
         match self {
@@ -53,7 +299,7 @@ impl JQCompile<StructValue, Script> for Path {
             }
             Path::Idx(idx) => {
                 let i64_type = compiler.context().i64_type();
-                let idx = i64_type.const_int(*idx as u64, false);
+                let idx = i64_type.const_int(*idx as u64, true);
                 let fun = compiler.get_function("jq_get_idx")?;
                 let r = compiler
                     .builder()
@@ -72,22 +318,929 @@ impl JQCompile<StructValue, Script> for Path {
                     None => Err(CompilerError::Generic),
                 }
             }
+            Path::Slice(from, to) => {
+                let i64_type = compiler.context().i64_type();
+                let from = i64_type.const_int(from.unwrap_or(0) as u64, true);
+                let to = i64_type.const_int(to.unwrap_or(std::i64::MAX) as u64, true);
+                compiler.call_json("jq_slice", &[val.into(), from.into(), to.into()])
+            }
             _ => Err(CompilerError::Generic),
         }
     }
 }
 
+impl JQGenerate<Script> for Path {
+    fn generate(
+        &self,
+        compiler: &mut Script,
+        val: StructValue,
+        k: &mut Cont<Script>,
+    ) -> Result<(), CompilerError> {
+        match self {
+            Path::Iter => {
+                let val = compiler.call_json("jq_iter_check", &[val.into()])?;
+                let val = compiler.check(val);
+                let len = compiler
+                    .call("jq_iter_len", &[val.into()])?
+                    .into_int_value();
+                compiler.build_loop(len, |compiler, i| {
+                    let v = compiler.call_json("jq_iter_get", &[val.into(), i.into()])?;
+                    k(compiler, v)
+                })
+            }
+            p => {
+                let v = p.compile(compiler, val)?;
+                let v = compiler.check(v);
+                k(compiler, v)
+            }
+        }
+    }
+}
+
+impl Path {
+    /// The path component for the segment, `Root` and `Iter` have none.
+    fn key(&self) -> Option<Value> {
+        match self {
+            Path::Key(k) => Some(Value::String(k.clone())),
+            Path::Idx(idx) => Some(Value::I64(*idx)),
+            Path::Slice(from, to) => {
+                let bound = |b: &Option<i64>| b.map(Value::I64).unwrap_or(Value::Null);
+                let mut key = Value::Object(Default::default());
+                if let Value::Object(o) = &mut key {
+                    o.insert("start".to_string(), bound(from));
+                    o.insert("end".to_string(), bound(to));
+                }
+                Some(key)
+            }
+            Path::Root | Path::Iter => None,
+        }
+    }
+
+    /// Path mode version of `generate`, see `Filter::generate_paths`.
+    fn generate_paths(
+        &self,
+        compiler: &mut Script,
+        path: StructValue,
+        k: &mut Cont<Script>,
+    ) -> Result<(), CompilerError> {
+        match self {
+            Path::Root => k(compiler, path),
+            Path::Iter => {
+                let val = compiler.path_value(path)?;
+                let val = compiler.call_json("jq_iter_check", &[val.into()])?;
+                let val = compiler.check(val);
+                let len = compiler
+                    .call("jq_iter_len", &[val.into()])?
+                    .into_int_value();
+                compiler.build_loop(len, |compiler, i| {
+                    let key = compiler.call_json("jq_iter_key", &[val.into(), i.into()])?;
+                    let p = compiler.call_json("jq_path_append", &[path.into(), key.into()])?;
+                    k(compiler, p)
+                })
+            }
+            p => {
+                // indexing the value reports the errors jq does
+                let val = compiler.path_value(path)?;
+                let v = p.compile(compiler, val)?;
+                compiler.check(v);
+                let key = compiler.const_json(p.key().unwrap());
+                let p = compiler.call_json("jq_path_append", &[path.into(), key.into()])?;
+                k(compiler, p)
+            }
+        }
+    }
+}
+
+fn generate_path(
+    compiler: &mut Script,
+    path: &[Path],
+    val: StructValue,
+    k: &mut Cont<Script>,
+) -> Result<(), CompilerError> {
+    match path.split_first() {
+        None => k(compiler, val),
+        Some((p, rest)) => p.generate(compiler, val, &mut |c: &mut Script, v: StructValue| {
+            generate_path(c, rest, v, k)
+        }),
+    }
+}
+
+fn generate_path_paths(
+    compiler: &mut Script,
+    segs: &[Path],
+    path: StructValue,
+    k: &mut Cont<Script>,
+) -> Result<(), CompilerError> {
+    match segs.split_first() {
+        None => k(compiler, path),
+        Some((p, rest)) => p.generate_paths(compiler, path, &mut |c: &mut Script, path: StructValue| {
+            generate_path_paths(c, rest, path, k)
+        }),
+    }
+}
+
+/// Builds an object for every combination of key and value outputs,
+/// `pairs` holds the keys and values chosen for the earlier entries. Each
+/// object is created once all of them are known, so the inserts can modify
+/// it in place.
+fn generate_object(
+    compiler: &mut Script,
+    entries: &[(Filter, Filter)],
+    pairs: &[(StructValue, StructValue)],
+    val: StructValue,
+    k: &mut Cont<Script>,
+) -> Result<(), CompilerError> {
+    match entries.split_first() {
+        None => {
+            let mut obj = compiler.call_json("jq_object_new", &[])?;
+            for &(key, v) in pairs {
+                let o = compiler.call_json(
+                    "jq_object_insert",
+                    &[obj.into(), key.into(), v.into()],
+                )?;
+                obj = compiler.check(o);
+            }
+            k(compiler, obj)
+        }
+        Some(((key, value), rest)) => key.generate(compiler, val, &mut |c: &mut Script, key: StructValue| {
+            value.generate(c, val, &mut |c: &mut Script, v: StructValue| {
+                let mut pairs = pairs.to_vec();
+                pairs.push((key, v));
+                generate_object(c, rest, &pairs, val, k)
+            })
+        }),
+    }
+}
+
+/// Matches `v` against `pattern`, stores the parts into the slots of the
+/// pattern's variables and runs `k` for every match (computed object keys
+/// can produce several).
+fn destructure(
+    compiler: &mut Script,
+    pattern: &Pattern,
+    v: StructValue,
+    k: &mut dyn FnMut(&mut Script) -> Result<(), CompilerError>,
+) -> Result<(), CompilerError> {
+    match pattern {
+        Pattern::Var(name) => {
+            let slot = compiler.variable(name)?;
+            compiler.builder().build_store(slot, v);
+            k(compiler)
+        }
+        Pattern::Array(elems) => destructure_array(compiler, elems, 0, v, k),
+        Pattern::Object(entries) => destructure_object(compiler, entries, v, k),
+    }
+}
+
+fn destructure_array(
+    compiler: &mut Script,
+    elems: &[Pattern],
+    idx: u64,
+    v: StructValue,
+    k: &mut dyn FnMut(&mut Script) -> Result<(), CompilerError>,
+) -> Result<(), CompilerError> {
+    match elems.split_first() {
+        None => k(compiler),
+        Some((p, rest)) => {
+            let i = compiler.context().i64_type().const_int(idx, false);
+            let e = compiler.call_json("jq_get_idx", &[v.into(), i.into()])?;
+            let e = compiler.check(e);
+            destructure(compiler, p, e, &mut |c: &mut Script| {
+                destructure_array(c, rest, idx + 1, v, k)
+            })
+        }
+    }
+}
+
+fn destructure_object(
+    compiler: &mut Script,
+    entries: &[(Filter, Option<String>, Option<Pattern>)],
+    v: StructValue,
+    k: &mut dyn FnMut(&mut Script) -> Result<(), CompilerError>,
+) -> Result<(), CompilerError> {
+    match entries.split_first() {
+        None => k(compiler),
+        Some(((key, var, pattern), rest)) => {
+            key.generate(compiler, v, &mut |c: &mut Script, key: StructValue| {
+                let e = c.call_json("jq_index", &[v.into(), key.into()])?;
+                let e = c.check(e);
+                if let Some(name) = var {
+                    let slot = c.variable(name)?;
+                    c.builder().build_store(slot, e);
+                }
+                match pattern {
+                    Some(p) => destructure(c, p, e, &mut |c: &mut Script| {
+                        destructure_object(c, rest, v, k)
+                    }),
+                    None => destructure_object(c, rest, v, k),
+                }
+            })
+        }
+    }
+}
+
+/// Runs `body` with a handler of its own: errors raised while it produces
+/// values go to `catch`, errors raised by the consumer `k` do not. The
+/// generators `body` was in the middle of are dropped before `catch` runs.
+fn generate_try<B, C>(
+    compiler: &mut Script,
+    k: &mut Cont<Script>,
+    body: B,
+    catch: C,
+) -> Result<(), CompilerError>
+where
+    B: FnOnce(&mut Script, &mut Cont<Script>) -> Result<(), CompilerError>,
+    C: FnOnce(&mut Script, StructValue, &mut Cont<Script>) -> Result<(), CompilerError>,
+{
+    let function = compiler.fn_value();
+    let catch_block = compiler.context().append_basic_block(&function, "catch");
+    let end_block = compiler.context().append_basic_block(&function, "try_end");
+    let slot = compiler.create_entry_block_alloca(compiler.json_struct().into(), "caught");
+    let mark = compiler.call("jq_gen_mark", &[])?;
+    let outer = compiler.handler.replace(Handler {
+        block: catch_block.clone(),
+        slot,
+    });
+    body(compiler, &mut |c: &mut Script, v: StructValue| {
+        let inner = std::mem::replace(&mut c.handler, outer.clone());
+        k(c, v)?;
+        c.handler = inner;
+        Ok(())
+    })?;
+    compiler.handler = outer;
+    compiler.builder().build_unconditional_branch(&end_block);
+
+    compiler.builder().position_at_end(&catch_block);
+    let drop = compiler.get_function("jq_gen_drop")?;
+    compiler.builder().build_call(drop, &[mark], "drop");
+    let err = compiler.builder().build_load(slot, "err").into_struct_value();
+    catch(compiler, err, k)?;
+    compiler.builder().build_unconditional_branch(&end_block);
+    compiler.builder().position_at_end(&end_block);
+    Ok(())
+}
+
+/// Runs `body` in `scope`, the consumer `k` still sees the current scope.
+fn generate_scoped<F>(
+    compiler: &mut Script,
+    scope: Scope,
+    k: &mut Cont<Script>,
+    body: F,
+) -> Result<(), CompilerError>
+where
+    F: FnOnce(&mut Script, &mut Cont<Script>) -> Result<(), CompilerError>,
+{
+    let outer = std::mem::replace(&mut compiler.scope, scope.clone());
+    body(compiler, &mut |c: &mut Script, v: StructValue| {
+        c.scope = outer.clone();
+        k(c, v)?;
+        c.scope = scope.clone();
+        Ok(())
+    })?;
+    compiler.scope = outer;
+    Ok(())
+}
+
+/// Runs `body` for every output of `source` with the variables of
+/// `patterns` bound to its parts, `k` is outside the variables' scope.
+fn generate_bind<F>(
+    compiler: &mut Script,
+    source: &Filter,
+    patterns: &[Pattern],
+    val: StructValue,
+    k: &mut Cont<Script>,
+    body: F,
+) -> Result<(), CompilerError>
+where
+    F: Fn(&mut Script, &mut Cont<Script>) -> Result<(), CompilerError>,
+{
+    let mut names = Vec::new();
+    patterns.iter().for_each(|p| p.variables(&mut names));
+    names.sort();
+    names.dedup();
+    source.generate(compiler, val, &mut |c: &mut Script, v: StructValue| {
+        let mut scope = c.scope.clone();
+        for name in &names {
+            let id = c.new_variable(name);
+            scope.variables.insert(name.clone(), id);
+        }
+        generate_scoped(c, scope, k, |c, k| match patterns {
+            [pattern] => destructure(c, pattern, v, &mut |c: &mut Script| body(c, k)),
+            _ => generate_alternatives(c, patterns, &names, v, &body, k),
+        })
+    })
+}
+
+/// The alternatives of `source as p1 ?// p2 | body`: all variables start out
+/// `null` and an error while matching `patterns[0]` or running `body` moves
+/// on to the next pattern, only the last one lets errors through.
+fn generate_alternatives(
+    compiler: &mut Script,
+    patterns: &[Pattern],
+    names: &[String],
+    v: StructValue,
+    body: &dyn Fn(&mut Script, &mut Cont<Script>) -> Result<(), CompilerError>,
+    k: &mut Cont<Script>,
+) -> Result<(), CompilerError> {
+    let null = compiler.const_json(Value::Null);
+    for name in names {
+        let slot = compiler.variable(name)?;
+        compiler.builder().build_store(slot, null);
+    }
+    match patterns.split_first() {
+        Some((pattern, [])) => destructure(compiler, pattern, v, &mut |c: &mut Script| body(c, k)),
+        Some((pattern, rest)) => generate_try(
+            compiler,
+            k,
+            |c, k| destructure(c, pattern, v, &mut |c: &mut Script| body(c, k)),
+            |c, _, k| generate_alternatives(c, rest, names, v, body, k),
+        ),
+        None => Ok(()),
+    }
+}
+
+/// Replaces the value at every path `lhs` produces for `val` with the first
+/// output `update` produces for it, a path `update` produces nothing for is
+/// deleted. `k` gets the updated `val`.
+fn generate_modify<F>(
+    compiler: &mut Script,
+    lhs: &Filter,
+    val: StructValue,
+    k: &mut Cont<Script>,
+    update: F,
+) -> Result<(), CompilerError>
+where
+    F: Fn(&mut Script, StructValue, &mut Cont<Script>) -> Result<(), CompilerError>,
+{
+    let json = compiler.json_struct().into();
+    let acc = compiler.create_entry_block_alloca(json, "modified");
+    let updated = compiler.create_entry_block_alloca(json, "updated");
+    compiler.builder().build_store(acc, val);
+    let outer = compiler.root.replace(val);
+    let root = compiler.const_json(Value::Array(Vec::new()));
+    lhs.generate_paths(compiler, root, &mut |c: &mut Script, path: StructValue| {
+        let cur = c.builder().build_load(acc, "cur");
+        let old = c.call_json("jq_getpath", &[cur, path.into()])?;
+        let old = c.check(old);
+        let no = c.const_json(Value::Bool(false));
+        c.builder().build_store(updated, no);
+        update(c, old, &mut |c: &mut Script, new: StructValue| {
+            let done = c.builder().build_load(updated, "done").into_struct_value();
+            c.build_branch(done, |c, done| {
+                if !done {
+                    let yes = c.const_json(Value::Bool(true));
+                    c.builder().build_store(updated, yes);
+                    let cur = c.builder().build_load(acc, "cur");
+                    let v = c.call_json("jq_setpath", &[cur, path.into(), new.into()])?;
+                    let v = c.check(v);
+                    c.builder().build_store(acc, v);
+                }
+                Ok(())
+            })
+        })?;
+        let done = c.builder().build_load(updated, "done").into_struct_value();
+        c.build_branch(done, |c, done| {
+            if !done {
+                let paths = c.call_json("jq_array_new", &[])?;
+                let push = c.get_function("jq_array_push")?;
+                c.builder().build_call(push, &[paths.into(), path.into()], "push");
+                let cur = c.builder().build_load(acc, "cur");
+                let v = c.call_json("jq_delpaths", &[cur, paths.into()])?;
+                let v = c.check(v);
+                c.builder().build_store(acc, v);
+            }
+            Ok(())
+        })
+    })?;
+    compiler.root = outer;
+    let result = compiler.builder().build_load(acc, "result").into_struct_value();
+    k(compiler, result)
+}
+
+/// Whether `name` is one of the math functions in `table`.
+fn is_math(table: &[Prototype], name: &str) -> bool {
+    table.iter().any(|p| p.name == name)
+}
+
+/// The runtime function implementing `op`.
+fn arith_function(op: Oper) -> &'static str {
+    match op {
+        Oper::Add => "jq_add",
+        Oper::Sub => "jq_sub",
+        Oper::Mul => "jq_mul",
+        Oper::Div => "jq_div",
+        Oper::Mod => "jq_mod",
+    }
+}
+
+/// Formats the outputs of the interpolated parts, the last part's outputs
+/// form the outer loop like in jq. `strings` holds the formatted later parts.
+fn generate_interpolation(
+    compiler: &mut Script,
+    format: StructValue,
+    parts: &[StringPart],
+    strings: &[StructValue],
+    val: StructValue,
+    k: &mut Cont<Script>,
+) -> Result<(), CompilerError> {
+    match parts.split_last() {
+        None => {
+            let mut strings = strings.iter().rev();
+            let mut s = *strings.next().unwrap();
+            for next in strings {
+                s = compiler.call_json("jq_add", &[s.into(), next.into()])?;
+            }
+            k(compiler, s)
+        }
+        Some((StringPart::Lit(lit), rest)) => {
+            let mut strings = strings.to_vec();
+            strings.push(compiler.const_json(Value::String(lit.clone())));
+            generate_interpolation(compiler, format, rest, &strings, val, k)
+        }
+        Some((StringPart::Interp(f), rest)) => {
+            f.generate(compiler, val, &mut |c: &mut Script, v: StructValue| {
+                let s = c.call_json("jq_format", &[v.into(), format.into()])?;
+                let mut strings = strings.to_vec();
+                strings.push(c.check(s));
+                generate_interpolation(c, format, rest, &strings, val, k)
+            })
+        }
+    }
+}
+
+/// Runs `op` on every combination of outputs of `lhs` and `rhs`, like jq
+/// the outputs of `rhs` form the outer loop.
+fn generate_binop<F>(
+    compiler: &mut Script,
+    lhs: &Filter,
+    rhs: &Filter,
+    val: StructValue,
+    k: &mut Cont<Script>,
+    mut op: F,
+) -> Result<(), CompilerError>
+where
+    F: FnMut(&mut Script, StructValue, StructValue) -> Result<StructValue, CompilerError>,
+{
+    rhs.generate(compiler, val, &mut |c: &mut Script, r: StructValue| {
+        lhs.generate(c, val, &mut |c: &mut Script, l: StructValue| {
+            let v = op(c, l, r)?;
+            k(c, v)
+        })
+    })
+}
+
+impl JQGenerate<Script> for Filter {
+    fn generate(
+        &self,
+        compiler: &mut Script,
+        val: StructValue,
+        k: &mut Cont<Script>,
+    ) -> Result<(), CompilerError> {
+        match self {
+            Filter::Path(path) => generate_path(compiler, path, val, k),
+            Filter::Pipe(lhs, rhs) => {
+                lhs.generate(compiler, val, &mut |c: &mut Script, v: StructValue| {
+                    rhs.generate(c, v, k)
+                })
+            }
+            Filter::Comma(lhs, rhs) => {
+                lhs.generate(compiler, val, k)?;
+                rhs.generate(compiler, val, k)
+            }
+            Filter::Try(body, catch) => generate_try(
+                compiler,
+                k,
+                |c, k| body.generate(c, val, k),
+                |c, err, k| match catch {
+                    Some(catch) => {
+                        let err = c.call_json("jq_catch", &[err.into()])?;
+                        catch.generate(c, err, k)
+                    }
+                    None => Ok(()),
+                },
+            ),
+            Filter::Arith(op, lhs, rhs) => {
+                let fun = arith_function(*op);
+                generate_binop(compiler, lhs, rhs, val, k, |c, l, r| {
+                    let v = c.call_json(fun, &[l.into(), r.into()])?;
+                    Ok(c.check(v))
+                })
+            }
+            Filter::Cmp(op, lhs, rhs) => {
+                generate_binop(compiler, lhs, rhs, val, k, |c, l, r| {
+                    let i64_type = c.context().i64_type();
+                    let ord = c
+                        .call("jq_compare", &[l.into(), r.into()])?
+                        .into_int_value();
+                    let b = c.builder().build_int_compare(
+                        op.predicate(),
+                        ord,
+                        i64_type.const_int(0, false),
+                        "cmp",
+                    );
+                    let b = c.builder().build_int_z_extend(b, i64_type, "b");
+                    c.call_json("jq_bool", &[b.into()])
+                })
+            }
+            Filter::Alternative(lhs, rhs) => {
+                let found = compiler.create_entry_block_alloca(compiler.json_struct().into(), "found");
+                let no = compiler.const_json(Value::Bool(false));
+                compiler.builder().build_store(found, no);
+                generate_try(
+                    compiler,
+                    k,
+                    |c, k| {
+                        lhs.generate(c, val, &mut |c: &mut Script, v: StructValue| {
+                            c.build_branch(v, |c, truthy| {
+                                if truthy {
+                                    let yes = c.const_json(Value::Bool(true));
+                                    c.builder().build_store(found, yes);
+                                    k(c, v)
+                                } else {
+                                    Ok(())
+                                }
+                            })
+                        })
+                    },
+                    |_, _, _| Ok(()),
+                )?;
+                let found = compiler.builder().build_load(found, "found").into_struct_value();
+                compiler.build_branch(found, |c, found| {
+                    if found {
+                        Ok(())
+                    } else {
+                        rhs.generate(c, val, k)
+                    }
+                })
+            }
+            Filter::Assign(AssignOp::Set, lhs, rhs) => {
+                rhs.generate(compiler, val, &mut |c: &mut Script, r: StructValue| {
+                    generate_modify(c, lhs, val, k, |c, _, k| k(c, r))
+                })
+            }
+            Filter::Assign(AssignOp::Update, lhs, rhs) => {
+                generate_modify(compiler, lhs, val, k, |c, old, k| rhs.generate(c, old, k))
+            }
+            Filter::Assign(AssignOp::Arith(op), lhs, rhs) => {
+                let fun = arith_function(*op);
+                rhs.generate(compiler, val, &mut |c: &mut Script, r: StructValue| {
+                    generate_modify(c, lhs, val, k, |c, old, k| {
+                        let v = c.call_json(fun, &[old.into(), r.into()])?;
+                        let v = c.check(v);
+                        k(c, v)
+                    })
+                })
+            }
+            Filter::Assign(AssignOp::Alt, lhs, rhs) => {
+                rhs.generate(compiler, val, &mut |c: &mut Script, r: StructValue| {
+                    generate_modify(c, lhs, val, k, |c, old, k| {
+                        c.build_branch(old, |c, truthy| k(c, if truthy { old } else { r }))
+                    })
+                })
+            }
+            Filter::And(lhs, rhs) => {
+                lhs.generate(compiler, val, &mut |c: &mut Script, l: StructValue| {
+                    c.build_branch(l, |c, truthy| {
+                        if truthy {
+                            rhs.generate(c, val, &mut |c: &mut Script, r: StructValue| {
+                                let b = c.to_bool(r)?;
+                                k(c, b)
+                            })
+                        } else {
+                            let b = c.const_json(Value::Bool(false));
+                            k(c, b)
+                        }
+                    })
+                })
+            }
+            Filter::Or(lhs, rhs) => {
+                lhs.generate(compiler, val, &mut |c: &mut Script, l: StructValue| {
+                    c.build_branch(l, |c, truthy| {
+                        if truthy {
+                            let b = c.const_json(Value::Bool(true));
+                            k(c, b)
+                        } else {
+                            rhs.generate(c, val, &mut |c: &mut Script, r: StructValue| {
+                                let b = c.to_bool(r)?;
+                                k(c, b)
+                            })
+                        }
+                    })
+                })
+            }
+            Filter::If(cond, then, otherwise) => {
+                cond.generate(compiler, val, &mut |c: &mut Script, v: StructValue| {
+                    c.build_branch(v, |c, truthy| match (truthy, otherwise) {
+                        (true, _) => then.generate(c, val, k),
+                        (false, Some(otherwise)) => otherwise.generate(c, val, k),
+                        (false, None) => k(c, val),
+                    })
+                })
+            }
+            Filter::Literal(v) => {
+                let v = compiler.const_json(v.clone());
+                k(compiler, v)
+            }
+            Filter::Array(body) => {
+                let array = compiler.call_json("jq_array_new", &[])?;
+                if let Some(body) = body {
+                    let push = compiler.get_function("jq_array_push")?;
+                    body.generate(compiler, val, &mut |c: &mut Script, v: StructValue| {
+                        c.builder().build_call(push, &[array.into(), v.into()], "push");
+                        Ok(())
+                    })?;
+                }
+                k(compiler, array)
+            }
+            Filter::Object(entries) => generate_object(compiler, entries, &[], val, k),
+            Filter::Format(name) => {
+                let format = compiler.const_format(name)?;
+                let s = compiler.call_json("jq_format", &[val.into(), format.into()])?;
+                let s = compiler.check(s);
+                k(compiler, s)
+            }
+            Filter::Interpolate(name, parts) => {
+                let format = compiler.const_format(name)?;
+                generate_interpolation(compiler, format, parts, &[], val, k)
+            }
+            Filter::Var(name) => {
+                let ptr = compiler.variable(name)?;
+                let v = compiler.builder().build_load(ptr, name).into_struct_value();
+                k(compiler, v)
+            }
+            Filter::Bind(source, patterns, body) => {
+                generate_bind(compiler, source, patterns, val, k, |c, k| {
+                    body.generate(c, val, k)
+                })
+            }
+            Filter::Reduce(source, patterns, init, update) => {
+                let acc = compiler.create_entry_block_alloca(compiler.json_struct().into(), "acc");
+                init.generate(compiler, val, &mut |c: &mut Script, init: StructValue| {
+                    c.builder().build_store(acc, init);
+                    // the state is `null` unless the update produces a value
+                    let mut store = |c: &mut Script, v: StructValue| -> Result<(), CompilerError> {
+                        c.builder().build_store(acc, v);
+                        Ok(())
+                    };
+                    generate_bind(c, source, patterns, val, &mut store, |c, k| {
+                        let state = c.builder().build_load(acc, "state").into_struct_value();
+                        let null = c.const_json(Value::Null);
+                        c.builder().build_store(acc, null);
+                        update.generate(c, state, k)
+                    })?;
+                    let result = c.builder().build_load(acc, "result").into_struct_value();
+                    k(c, result)
+                })
+            }
+            Filter::Foreach(source, patterns, init, update, extract) => {
+                let acc = compiler.create_entry_block_alloca(compiler.json_struct().into(), "acc");
+                init.generate(compiler, val, &mut |c: &mut Script, init: StructValue| {
+                    c.builder().build_store(acc, init);
+                    generate_bind(c, source, patterns, val, k, |c, k| {
+                        let state = c.builder().build_load(acc, "state").into_struct_value();
+                        update.generate(c, state, &mut |c: &mut Script, v: StructValue| {
+                            c.builder().build_store(acc, v);
+                            match extract {
+                                Some(extract) => extract.generate(c, v, k),
+                                None => k(c, v),
+                            }
+                        })
+                    })
+                })
+            }
+            Filter::Def(def, rest) => {
+                let mut scope = compiler.scope.clone();
+                let def = Rc::new(Closure {
+                    code: (**def).clone(),
+                    scope: compiler.scope.clone(),
+                });
+                scope
+                    .functions
+                    .insert((def.code.name.clone(), def.code.params.len()), Callable::Def(def));
+                generate_scoped(compiler, scope, k, |c, k| rest.generate(c, val, k))
+            }
+            Filter::Call(name, args) => {
+                match compiler.scope.functions.get(&(name.clone(), args.len())).cloned() {
+                    Some(Callable::Param(arg)) => {
+                        let scope = arg.scope.clone();
+                        generate_scoped(compiler, scope, k, |c, k| arg.code.generate(c, val, k))
+                    }
+                    Some(Callable::Def(def)) => {
+                        compiler.generate_def_call(self, &def, args, val, false, k)
+                    }
+                    Some(Callable::Dynamic(id)) => {
+                        compiler.generate_dynamic_call(id, val, false, k)
+                    }
+                    None => compiler.generate_call(name, args, val, k),
+                }
+            }
+        }
+    }
+}
+
+impl Filter {
+    /// Generates the filter in path mode: instead of values the filter
+    /// produces the paths of those values in `Script::root`, starting from
+    /// the path of its input. Like in jq, filters that compute new values
+    /// fail when they produce one.
+    pub fn generate_paths(
+        &self,
+        compiler: &mut Script,
+        path: StructValue,
+        k: &mut Cont<Script>,
+    ) -> Result<(), CompilerError> {
+        match self {
+            Filter::Path(segs) => generate_path_paths(compiler, segs, path, k),
+            Filter::Pipe(lhs, rhs) => {
+                lhs.generate_paths(compiler, path, &mut |c: &mut Script, p: StructValue| {
+                    rhs.generate_paths(c, p, k)
+                })
+            }
+            Filter::Comma(lhs, rhs) => {
+                lhs.generate_paths(compiler, path, k)?;
+                rhs.generate_paths(compiler, path, k)
+            }
+            Filter::Try(body, catch) => generate_try(
+                compiler,
+                k,
+                |c, k| body.generate_paths(c, path, k),
+                |c, err, _| match catch {
+                    Some(catch) => {
+                        let err = c.call_json("jq_catch", &[err.into()])?;
+                        catch.generate(c, err, &mut |c: &mut Script, v: StructValue| {
+                            c.invalid_path(v)
+                        })
+                    }
+                    None => Ok(()),
+                },
+            ),
+            Filter::Alternative(lhs, rhs) => {
+                let found = compiler.create_entry_block_alloca(compiler.json_struct().into(), "found");
+                let no = compiler.const_json(Value::Bool(false));
+                compiler.builder().build_store(found, no);
+                generate_try(
+                    compiler,
+                    k,
+                    |c, k| {
+                        lhs.generate_paths(c, path, &mut |c: &mut Script, p: StructValue| {
+                            let v = c.path_value(p)?;
+                            c.build_branch(v, |c, truthy| {
+                                if truthy {
+                                    let yes = c.const_json(Value::Bool(true));
+                                    c.builder().build_store(found, yes);
+                                    k(c, p)
+                                } else {
+                                    Ok(())
+                                }
+                            })
+                        })
+                    },
+                    |_, _, _| Ok(()),
+                )?;
+                let found = compiler.builder().build_load(found, "found").into_struct_value();
+                compiler.build_branch(found, |c, found| {
+                    if found {
+                        Ok(())
+                    } else {
+                        rhs.generate_paths(c, path, k)
+                    }
+                })
+            }
+            Filter::If(cond, then, otherwise) => {
+                let val = compiler.path_value(path)?;
+                cond.generate(compiler, val, &mut |c: &mut Script, v: StructValue| {
+                    c.build_branch(v, |c, truthy| match (truthy, otherwise) {
+                        (true, _) => then.generate_paths(c, path, k),
+                        (false, Some(otherwise)) => otherwise.generate_paths(c, path, k),
+                        (false, None) => k(c, path),
+                    })
+                })
+            }
+            Filter::Bind(source, patterns, body) => {
+                let val = compiler.path_value(path)?;
+                generate_bind(compiler, source, patterns, val, k, |c, k| {
+                    body.generate_paths(c, path, k)
+                })
+            }
+            Filter::Def(def, rest) => {
+                let mut scope = compiler.scope.clone();
+                let def = Rc::new(Closure {
+                    code: (**def).clone(),
+                    scope: compiler.scope.clone(),
+                });
+                scope
+                    .functions
+                    .insert((def.code.name.clone(), def.code.params.len()), Callable::Def(def));
+                generate_scoped(compiler, scope, k, |c, k| rest.generate_paths(c, path, k))
+            }
+            Filter::Call(name, args) => {
+                match compiler.scope.functions.get(&(name.clone(), args.len())).cloned() {
+                    Some(Callable::Param(arg)) => {
+                        let scope = arg.scope.clone();
+                        generate_scoped(compiler, scope, k, |c, k| arg.code.generate_paths(c, path, k))
+                    }
+                    Some(Callable::Def(def)) => {
+                        compiler.generate_def_call(self, &def, args, path, true, k)
+                    }
+                    Some(Callable::Dynamic(id)) => {
+                        compiler.generate_dynamic_call(id, path, true, k)
+                    }
+                    None => compiler.generate_call_paths(name, args, path, k),
+                }
+            }
+            f => {
+                let val = compiler.path_value(path)?;
+                f.generate(compiler, val, &mut |c: &mut Script, v: StructValue| {
+                    c.invalid_path(v)
+                })
+            }
+        }
+    }
+}
+
 type MainFunc = unsafe extern "C" fn(Wrap) -> Wrap;
 
+/// Where generated code jumps when a runtime function reports an error, the
+/// failing `Wrap` is stored in `slot` first.
+#[derive(Debug, Clone)]
+pub struct Handler {
+    pub block: BasicBlock,
+    pub slot: PointerValue,
+}
+
+/// A function compiled for a `def`. It keeps the closures it was compiled
+/// for, their addresses are part of the key it is cached under and must not
+/// be reused by other closures.
+#[derive(Clone)]
+pub struct Specialization {
+    pub function: FunctionValue,
+    /// Where compiled code finds its `Entry` for `jq_gen_new`.
+    pub entry: u64,
+    /// The ids of the variables it takes after the input.
+    pub captures: Vec<usize>,
+    pub def: Rc<Closure<FuncDef>>,
+    /// `None` for the version that takes a closure for every filter
+    /// parameter after the captures.
+    pub args: Option<Vec<Rc<Closure<Filter>>>>,
+}
+
+/// Adds the calls in `f` whose outputs are the outputs of `f` to `calls`.
+/// Only filters that neither change nor catch the outputs of the parts are
+/// followed, a `?//` binding retries its body on errors.
+fn tail_calls(f: &Filter, calls: &mut HashSet<usize>) {
+    match f {
+        Filter::Call(..) => {
+            calls.insert(f as *const Filter as usize);
+        }
+        Filter::Pipe(_, rhs) => tail_calls(rhs, calls),
+        Filter::Comma(lhs, rhs) => {
+            tail_calls(lhs, calls);
+            tail_calls(rhs, calls);
+        }
+        Filter::If(_, then, otherwise) => {
+            tail_calls(then, calls);
+            if let Some(otherwise) = otherwise {
+                tail_calls(otherwise, calls);
+            }
+        }
+        Filter::Bind(_, patterns, body) if patterns.len() == 1 => tail_calls(body, calls),
+        Filter::Def(_, rest) => tail_calls(rest, calls),
+        _ => (),
+    }
+}
+
 pub struct Script {
-    pub script: Vec<Path>,
+    pub script: Filter,
     pub context: Context,
     pub module: Module,
     pub builder: Builder,
     pub execution_engine: ExecutionEngine,
-    pub variables: HashMap<String, PointerValue>,
+    pub scope: Scope,
+    /// Where the function being compiled keeps the variables, by id.
+    pub slots: HashMap<usize, PointerValue>,
+    pub next_variable: usize,
+    /// The functions compiled for a `def` and the closures passed to it (or
+    /// `None` for the version taking them at runtime), in path mode or not.
+    pub specializations: HashMap<(usize, Option<Vec<usize>>, bool), Specialization>,
+    /// The `def`s whose functions are being compiled, by address.
+    pub compiling: Vec<usize>,
+    /// The calls whose outputs are the outputs of the function being
+    /// compiled, by address (see `tail_calls`). They run on its generator
+    /// instead of one of their own.
+    pub tail: HashSet<usize>,
+    /// The names of the entry points of the compiled functions and where
+    /// compiled code finds their addresses, which are filled in once the
+    /// module is compiled.
+    pub entries: Vec<(String, Box<usize>)>,
+    /// The value the paths in path mode point into.
+    pub root: Option<StructValue>,
     pub fn_value_opt: Option<FunctionValue>,
     pub json_struct: StructType,
+    pub handler: Option<Handler>,
+    /// Values of literals in the script, compiled code points into them.
+    pub constants: Vec<Box<Value>>,
+    /// The regexes given as literals, compiled along with the script.
+    pub regexes: Vec<Box<Regex>>,
 }
 
 impl Compiler for Script {
@@ -106,7 +1259,7 @@ impl Compiler for Script {
 }
 
 impl Script {
-    pub fn from_path(script: Vec<Path>) -> Self {
+    pub fn from_filter(script: Filter) -> Self {
         let context = Context::create();
         let module = context.create_module("jq");
         let builder = context.create_builder();
@@ -121,18 +1274,1007 @@ impl Script {
             module,
             builder,
             execution_engine,
-            variables: HashMap::new(),
+            scope: Scope::default(),
+            slots: HashMap::new(),
+            next_variable: 0,
+            specializations: HashMap::new(),
+            compiling: Vec::new(),
+            tail: HashSet::new(),
+            entries: Vec::new(),
+            root: None,
             fn_value_opt: None,
             json_struct,
+            handler: None,
+            constants: Vec::new(),
+            regexes: Vec::new(),
             script,
         };
-        for p in &STDLIB {
+        for p in STDLIB.iter().chain(&MATH_1).chain(&MATH_2).chain(&MATH_3) {
             p.compile::<Script>(&compiler);
         }
         compiler
     }
 
+    /// Returns the `FunctionValue` representing the function being compiled.
+    #[inline]
+    fn fn_value(&self) -> FunctionValue {
+        self.fn_value_opt.unwrap()
+    }
+
+    pub fn create_entry_block_alloca(&self, ty: BasicTypeEnum, name: &str) -> PointerValue {
+        let builder = self.context.create_builder();
+
+        let entry = self.fn_value().get_entry_basic_block().unwrap();
+
+        match entry.get_first_instruction() {
+            Some(first_instr) => builder.position_before(&first_instr),
+            None => builder.position_at_end(&entry),
+        }
+
+        builder.build_alloca(ty, name)
+    }
+
+    /// Allocates a slot for a new variable and returns its id, binding the
+    /// variable to `name` is up to the caller.
+    pub fn new_variable(&mut self, name: &str) -> usize {
+        let slot = self.create_entry_block_alloca(self.json_struct.into(), name);
+        let id = self.next_variable;
+        self.next_variable += 1;
+        self.slots.insert(id, slot);
+        id
+    }
+
+    /// The slot of the variable `name` in the function being compiled.
+    pub fn variable(&self, name: &str) -> Result<PointerValue, CompilerError> {
+        self.scope
+            .variables
+            .get(name)
+            .and_then(|id| self.slots.get(id))
+            .cloned()
+            .ok_or_else(|| CompilerError::UnknownVariable(name.to_string()))
+    }
+
+    /// The value at `path` in `root`, for filters in path mode.
+    pub fn path_value(&self, path: StructValue) -> Result<StructValue, CompilerError> {
+        let root = self.root.ok_or(CompilerError::Generic)?;
+        let v = self.call_json("jq_getpath", &[root.into(), path.into()])?;
+        Ok(self.check(v))
+    }
+
+    /// Fails with jq's error for a value produced in path mode.
+    pub fn invalid_path(&self, v: StructValue) -> Result<(), CompilerError> {
+        let err = self.call_json("jq_invalid_path", &[v.into()])?;
+        self.check(err);
+        Ok(())
+    }
+
+    /// The name of the format `@name` as a constant.
+    fn const_format(&mut self, name: &str) -> Result<StructValue, CompilerError> {
+        if !FORMATS.contains(&name) {
+            return Err(CompilerError::UnknownFunction(format!("@{}", name)));
+        }
+        Ok(self.const_json(Value::String(name.to_string())))
+    }
+
+    /// Embeds `v` as a constant `Wrap`, the value lives as long as the script.
+    pub fn const_json(&mut self, v: Value) -> StructValue {
+        let i64_type = self.context.i64_type();
+        let v = Box::new(v);
+        let ptr = &*v as *const Value as u64;
+        self.constants.push(v);
+        self.json_struct.const_named_struct(&[
+            i64_type.const_int(0, false).into(),
+            i64_type.const_int(ptr, false).into(),
+        ])
+    }
+
+    /// Calls the runtime function `name` and returns its result.
+    pub fn call(
+        &self,
+        name: &str,
+        args: &[BasicValueEnum],
+    ) -> Result<BasicValueEnum, CompilerError> {
+        let fun = self.get_function(name)?;
+        self.builder
+            .build_call(fun, args, name)
+            .try_as_basic_value()
+            .left()
+            .ok_or(CompilerError::Generic)
+    }
+
+    /// Calls a runtime function that returns a `Wrap`.
+    pub fn call_json(
+        &self,
+        name: &str,
+        args: &[BasicValueEnum],
+    ) -> Result<StructValue, CompilerError> {
+        Ok(self.call(name, args)?.into_struct_value())
+    }
+
+    /// Branches to the current error handler if `w` carries an error and
+    /// continues with `w` otherwise.
+    pub fn check(&self, w: StructValue) -> StructValue {
+        let handler = self.handler.as_ref().unwrap();
+        let function = self.fn_value();
+        let error = self
+            .builder
+            .build_extract_value(w, 0, "error")
+            .unwrap()
+            .into_int_value();
+        let zero = self.context.i64_type().const_int(0, false);
+        let failed = self
+            .builder
+            .build_int_compare(IntPredicate::NE, error, zero, "failed");
+        let fail_block = self.context.append_basic_block(&function, "fail");
+        let ok_block = self.context.append_basic_block(&function, "ok");
+        self.builder
+            .build_conditional_branch(failed, &fail_block, &ok_block);
+        self.builder.position_at_end(&fail_block);
+        self.builder.build_store(handler.slot, w);
+        self.builder.build_unconditional_branch(&handler.block);
+        self.builder.position_at_end(&ok_block);
+        w
+    }
+
+    /// Converts `v` to `true` or `false` by its truthiness.
+    pub fn to_bool(&self, v: StructValue) -> Result<StructValue, CompilerError> {
+        let truthy = self.call("jq_truthy", &[v.into()])?;
+        self.call_json("jq_bool", &[truthy])
+    }
+
+    /// Branches on the truthiness of `v`, `arm` is called with `true` to emit
+    /// the code run for truthy values and with `false` for `false` and `null`.
+    pub fn build_branch<F>(&mut self, v: StructValue, mut arm: F) -> Result<(), CompilerError>
+    where
+        F: FnMut(&mut Script, bool) -> Result<(), CompilerError>,
+    {
+        let function = self.fn_value();
+        let truthy = self.call("jq_truthy", &[v.into()])?.into_int_value();
+        let cond = self.builder.build_int_compare(
+            IntPredicate::NE,
+            truthy,
+            self.context.i64_type().const_int(0, false),
+            "truthy",
+        );
+        let then_block = self.context.append_basic_block(&function, "then");
+        let else_block = self.context.append_basic_block(&function, "else");
+        let end_block = self.context.append_basic_block(&function, "end");
+        self.builder
+            .build_conditional_branch(cond, &then_block, &else_block);
+
+        self.builder.position_at_end(&then_block);
+        arm(self, true)?;
+        self.builder.build_unconditional_branch(&end_block);
+
+        self.builder.position_at_end(&else_block);
+        arm(self, false)?;
+        self.builder.build_unconditional_branch(&end_block);
+
+        self.builder.position_at_end(&end_block);
+        Ok(())
+    }
+
+    /// Emits a loop running `body` for every index in `0..len`.
+    pub fn build_loop<F>(&mut self, len: IntValue, mut body: F) -> Result<(), CompilerError>
+    where
+        F: FnMut(&mut Script, IntValue) -> Result<(), CompilerError>,
+    {
+        let i64_type = self.context.i64_type();
+        let function = self.fn_value();
+        let idx = self.create_entry_block_alloca(i64_type.into(), "idx");
+        self.builder.build_store(idx, i64_type.const_int(0, false));
+
+        let cond_block = self.context.append_basic_block(&function, "loop_cond");
+        let body_block = self.context.append_basic_block(&function, "loop_body");
+        let done_block = self.context.append_basic_block(&function, "loop_done");
+        self.builder.build_unconditional_branch(&cond_block);
+
+        self.builder.position_at_end(&cond_block);
+        let i = self.builder.build_load(idx, "i").into_int_value();
+        let more = self
+            .builder
+            .build_int_compare(IntPredicate::SLT, i, len, "more");
+        self.builder
+            .build_conditional_branch(more, &body_block, &done_block);
+
+        self.builder.position_at_end(&body_block);
+        body(self, i)?;
+        let i = self.builder.build_load(idx, "i").into_int_value();
+        let next = self
+            .builder
+            .build_int_add(i, i64_type.const_int(1, false), "next");
+        self.builder.build_store(idx, next);
+        self.builder.build_unconditional_branch(&cond_block);
+
+        self.builder.position_at_end(&done_block);
+        Ok(())
+    }
+
+    /// Calls `def` with `args` as its filter arguments, `call` is the node
+    /// the call comes from. `k` gets the outputs as the function produces
+    /// them: it runs on a generator of its own (see `jq_gen_new`), unless
+    /// its outputs are the outputs of the function being compiled. Then it
+    /// runs on the generator of that function and hands them on itself.
+    ///
+    /// Recursion passing new closures at every level, like `def f(g): f(g |
+    /// g)`, would compile new functions forever. A `def` called while its
+    /// function is being compiled gets the version taking its filter
+    /// arguments at runtime instead. In path mode `val` and the outputs are
+    /// paths.
+    fn generate_def_call(
+        &mut self,
+        call: &Filter,
+        def: &Rc<Closure<FuncDef>>,
+        args: &[Filter],
+        val: StructValue,
+        paths: bool,
+        k: &mut Cont<Script>,
+    ) -> Result<(), CompilerError> {
+        let closure = |arg: &Filter| {
+            Rc::new(Closure {
+                code: arg.clone(),
+                scope: self.scope.clone(),
+            })
+        };
+        let args: Vec<_> = args
+            .iter()
+            .map(|arg| match arg {
+                // passing a parameter on reuses its closure, this keeps
+                // recursive functions from being specialized again
+                Filter::Call(name, params) if params.is_empty() => {
+                    match self.scope.functions.get(&(name.clone(), 0)) {
+                        Some(Callable::Param(arg)) => arg.clone(),
+                        _ => closure(arg),
+                    }
+                }
+                _ => closure(arg),
+            })
+            .collect();
+        let key = (
+            Rc::as_ptr(def) as usize,
+            Some(args.iter().map(|a| Rc::as_ptr(a) as usize).collect()),
+            paths,
+        );
+        let recursive = !self.specializations.contains_key(&key)
+            && self.compiling.contains(&(Rc::as_ptr(def) as usize));
+        let (f, closures) = if recursive {
+            let closures = args
+                .iter()
+                .map(|arg| self.build_closure(arg))
+                .collect::<Result<Vec<_>, _>>()?;
+            (self.specialize(def, None, paths)?, closures)
+        } else {
+            (self.specialize(def, Some(args), paths)?, Vec::new())
+        };
+
+        let mut call_args: Vec<BasicValueEnum> = f
+            .captures
+            .iter()
+            .map(|id| self.builder.build_load(self.slots[id], "capture"))
+            .collect();
+        call_args.extend(closures);
+        if paths {
+            call_args.push(self.root.ok_or(CompilerError::Generic)?.into());
+        }
+        if self.tail.contains(&(call as *const Filter as usize)) {
+            call_args.insert(0, val.into());
+            let status = self
+                .builder
+                .build_call(f.function, &call_args, &f.def.code.name)
+                .try_as_basic_value()
+                .left()
+                .ok_or(CompilerError::Generic)?
+                .into_struct_value();
+            self.check(status);
+            return Ok(());
+        }
+        let entry = self.context.i64_type().const_int(f.entry, false);
+        let gen = self.call("jq_gen_new", &[entry.into(), val.into()])?;
+        let push = self.get_function("jq_gen_push")?;
+        for arg in call_args {
+            self.builder.build_call(push, &[gen, arg], "push");
+        }
+        self.generate_outputs(gen.into_int_value(), k)
+    }
+
+    /// Calls the filter parameter whose closure the variable `id` holds, in
+    /// path mode with `val` a path.
+    fn generate_dynamic_call(
+        &mut self,
+        id: usize,
+        val: StructValue,
+        paths: bool,
+        k: &mut Cont<Script>,
+    ) -> Result<(), CompilerError> {
+        let closure = self.builder.build_load(self.slots[&id], "closure");
+        let mode = self.context.i64_type().const_int(paths as u64, false);
+        let gen = self.call("jq_gen_closure", &[closure, val.into(), mode.into()])?;
+        if paths {
+            let root = self.root.ok_or(CompilerError::Generic)?;
+            let push = self.get_function("jq_gen_push")?;
+            self.builder.build_call(push, &[gen, root.into()], "push");
+        }
+        self.generate_outputs(gen.into_int_value(), k)
+    }
+
+    /// Runs `k` on every output of the generator `gen`, then drops it and
+    /// checks the status its function returned.
+    fn generate_outputs(
+        &mut self,
+        gen: IntValue,
+        k: &mut Cont<Script>,
+    ) -> Result<(), CompilerError> {
+        let function = self.fn_value();
+        let next_block = self.context.append_basic_block(&function, "next");
+        let output_block = self.context.append_basic_block(&function, "output");
+        let done_block = self.context.append_basic_block(&function, "done");
+        self.builder.build_unconditional_branch(&next_block);
+
+        self.builder.position_at_end(&next_block);
+        let v = self.call_json("jq_gen_next", &[gen.into()])?;
+        let done = self.call("jq_gen_done", &[gen.into()])?.into_int_value();
+        let zero = self.context.i64_type().const_int(0, false);
+        let done = self
+            .builder
+            .build_int_compare(IntPredicate::NE, done, zero, "done");
+        self.builder
+            .build_conditional_branch(done, &done_block, &output_block);
+
+        self.builder.position_at_end(&output_block);
+        k(self, v)?;
+        self.builder.build_unconditional_branch(&next_block);
+
+        self.builder.position_at_end(&done_block);
+        let drop = self.get_function("jq_gen_drop")?;
+        self.builder.build_call(drop, &[gen.into()], "drop");
+        self.check(v);
+        Ok(())
+    }
+
+    /// `arg` as a value for a function taking its filter arguments at
+    /// runtime: an array of where the `Entry`s of the functions compiled for
+    /// it without and with path mode are and the variables they capture,
+    /// see `jq_gen_closure`.
+    fn build_closure(
+        &mut self,
+        arg: &Rc<Closure<Filter>>,
+    ) -> Result<BasicValueEnum, CompilerError> {
+        if let Filter::Call(name, params) = &arg.code {
+            if let (Some(Callable::Dynamic(id)), true) =
+                (arg.scope.functions.get(&(name.clone(), 0)), params.is_empty())
+            {
+                return Ok(self.builder.build_load(self.slots[id], "closure"));
+            }
+        }
+        let def = Rc::new(Closure {
+            code: FuncDef {
+                name: "closure".to_string(),
+                params: Vec::new(),
+                body: arg.code.clone(),
+            },
+            scope: arg.scope.clone(),
+        });
+        let closure = self.call_json("jq_array_new", &[])?;
+        let push = self.get_function("jq_array_push")?;
+        let mut captures = Vec::new();
+        for paths in &[false, true] {
+            let f = self.specialize(&def, Some(Vec::new()), *paths)?;
+            let entry = self.const_json(Value::I64(f.entry as i64));
+            self.builder
+                .build_call(push, &[closure.into(), entry.into()], "push");
+            captures = f.captures;
+        }
+        for id in &captures {
+            let v = self.builder.build_load(self.slots[id], "capture");
+            self.builder.build_call(push, &[closure.into(), v], "push");
+        }
+        Ok(closure.into())
+    }
+
+    /// Returns the function compiled for `def` with `args`, compiling it
+    /// first if needed. Without `args` it takes a closure for every filter
+    /// parameter (see `build_closure`) after the captures. In path mode the
+    /// root the paths point into comes last.
+    fn specialize(
+        &mut self,
+        def: &Rc<Closure<FuncDef>>,
+        args: Option<Vec<Rc<Closure<Filter>>>>,
+        paths: bool,
+    ) -> Result<Specialization, CompilerError> {
+        let key = (
+            Rc::as_ptr(def) as usize,
+            args.as_ref()
+                .map(|args| args.iter().map(|a| Rc::as_ptr(a) as usize).collect()),
+            paths,
+        );
+        if let Some(f) = self.specializations.get(&key) {
+            return Ok(f.clone());
+        }
+        let mut ids = BTreeSet::new();
+        let mut seen = HashSet::new();
+        def.scope.captures(&mut ids, &mut seen);
+        args.iter()
+            .flatten()
+            .for_each(|a| a.scope.captures(&mut ids, &mut seen));
+        let captures: Vec<usize> = ids.into_iter().collect();
+        let closures = if args.is_some() {
+            0
+        } else {
+            def.code.params.len()
+        };
+
+        let name = format!("{}/{}", def.code.name, def.code.params.len());
+        let params: Vec<BasicTypeEnum> =
+            vec![self.json_struct.into(); 1 + captures.len() + closures + paths as usize];
+        let function = self
+            .module
+            .add_function(&name, self.json_struct.fn_type(&params, false), None);
+        let specialization = Specialization {
+            function,
+            entry: self.build_entry(function, &name, params.len() - 1)?,
+            captures: captures.clone(),
+            def: def.clone(),
+            args: args.clone(),
+        };
+        self.specializations.insert(key, specialization.clone());
+
+        // put the function we are in aside while compiling the new one
+        let block = self.builder.get_insert_block();
+        let fn_value_opt = self.fn_value_opt.replace(function);
+        let handler = self.handler.take();
+        let slots = std::mem::replace(&mut self.slots, HashMap::new());
+        let scope = self.scope.clone();
+        let mut calls = HashSet::new();
+        tail_calls(&def.code.body, &mut calls);
+        let tail = std::mem::replace(&mut self.tail, calls);
+        let root = self.root.take();
+        self.compiling.push(Rc::as_ptr(def) as usize);
+
+        let entry = self.context.append_basic_block(&function, "entry");
+        let fail_block = self.context.append_basic_block(&function, "error");
+        self.builder.position_at_end(&entry);
+        let slot = self.create_entry_block_alloca(self.json_struct.into(), "error");
+        self.handler = Some(Handler {
+            block: fail_block.clone(),
+            slot,
+        });
+        let name = self.const_json(Value::String(name));
+        let entered = self.call_json("jq_enter", &[name.into()])?;
+        self.check(entered);
+        for (i, id) in captures.iter().enumerate() {
+            let v = function.get_nth_param(i as u32 + 1).unwrap();
+            let capture = self.create_entry_block_alloca(self.json_struct.into(), "capture");
+            self.builder.build_store(capture, v);
+            self.slots.insert(*id, capture);
+        }
+        self.scope = def.scope.clone();
+        self.scope.functions.insert(
+            (def.code.name.clone(), def.code.params.len()),
+            Callable::Def(def.clone()),
+        );
+        match args {
+            Some(args) => {
+                for (param, arg) in def.code.params.iter().zip(args) {
+                    self.scope
+                        .functions
+                        .insert((param.clone(), 0), Callable::Param(arg));
+                }
+            }
+            None => {
+                for (i, param) in def.code.params.iter().enumerate() {
+                    let v = function
+                        .get_nth_param((1 + captures.len() + i) as u32)
+                        .unwrap();
+                    let id = self.new_variable(param);
+                    self.builder.build_store(self.slots[&id], v);
+                    self.scope
+                        .functions
+                        .insert((param.clone(), 0), Callable::Dynamic(id));
+                }
+            }
+        }
+
+        let input = function.get_nth_param(0).unwrap().into_struct_value();
+        let yield_fn = self.get_function("jq_yield")?;
+        let mut emit = |c: &mut Script, v: StructValue| -> Result<(), CompilerError> {
+            c.builder().build_call(yield_fn, &[v.into()], "yield");
+            Ok(())
+        };
+        let result = if paths {
+            let root = function.get_nth_param(params.len() as u32 - 1).unwrap();
+            self.root = Some(root.into_struct_value());
+            def.code.body.generate_paths(self, input, &mut emit)
+        } else {
+            def.code.body.generate(self, input, &mut emit)
+        };
+        self.builder.build_return(Some(&input));
+        self.builder.position_at_end(&fail_block);
+        let err = self.builder.build_load(slot, "err");
+        self.builder.build_return(Some(&err));
+
+        self.compiling.pop();
+        self.root = root;
+        self.tail = tail;
+        self.scope = scope;
+        self.slots = slots;
+        self.handler = handler;
+        self.fn_value_opt = fn_value_opt;
+        if let Some(block) = block {
+            self.builder.position_at_end(&block);
+        }
+        result?;
+        Ok(specialization)
+    }
+
+    /// Adds the `Entry` generators run `function` with, which loads the
+    /// `count` values `function` takes after the input from the generator.
+    /// Returns where compiled code finds its address.
+    fn build_entry(
+        &mut self,
+        function: FunctionValue,
+        name: &str,
+        count: usize,
+    ) -> Result<u64, CompilerError> {
+        let i64_type = self.context.i64_type();
+        let fn_type = self
+            .json_struct
+            .fn_type(&[self.json_struct.into(), i64_type.into()], false);
+        // entry points are looked up by name, it must be unique
+        let name = format!("{}/entry{}", name, self.entries.len());
+        let entry = self.module.add_function(&name, fn_type, None);
+        let builder = self.context.create_builder();
+        builder.position_at_end(&self.context.append_basic_block(&entry, "entry"));
+        let gen = entry.get_nth_param(1).unwrap();
+        let arg = self.get_function("jq_gen_arg")?;
+        let mut args = vec![entry.get_nth_param(0).unwrap()];
+        for i in 0..count {
+            let idx = i64_type.const_int(i as u64, false);
+            let v = builder
+                .build_call(arg, &[gen, idx.into()], "arg")
+                .try_as_basic_value()
+                .left()
+                .ok_or(CompilerError::Generic)?;
+            args.push(v);
+        }
+        let status = builder
+            .build_call(function, &args, "call")
+            .try_as_basic_value()
+            .left()
+            .ok_or(CompilerError::Generic)?;
+        builder.build_return(Some(&status));
+        let address = Box::new(0);
+        let ptr = &*address as *const usize as u64;
+        self.entries.push((name, address));
+        Ok(ptr)
+    }
+
+    /// `limit(n; f)` for the `n` `jq_limit` computed: once `k` got `n`
+    /// outputs the generated code jumps out of `f`, the generators `f` is in
+    /// the middle of are dropped. In path mode `val` and the outputs are
+    /// paths.
+    fn generate_limit(
+        &mut self,
+        f: &Filter,
+        val: StructValue,
+        n: IntValue,
+        paths: bool,
+        k: &mut Cont<Script>,
+    ) -> Result<(), CompilerError> {
+        let i64_type = self.context.i64_type();
+        let zero = i64_type.const_int(0, false);
+        let function = self.fn_value();
+        let limit_block = self.context.append_basic_block(&function, "limit");
+        let stop_block = self.context.append_basic_block(&function, "limit_stop");
+        let end_block = self.context.append_basic_block(&function, "limit_end");
+        let count = self.create_entry_block_alloca(i64_type.into(), "count");
+        let some = self
+            .builder
+            .build_int_compare(IntPredicate::NE, n, zero, "some");
+        self.builder
+            .build_conditional_branch(some, &limit_block, &end_block);
+
+        self.builder.position_at_end(&limit_block);
+        self.builder.build_store(count, zero);
+        let mark = self.call("jq_gen_mark", &[])?;
+        let mut limited = |c: &mut Script, v: StructValue| -> Result<(), CompilerError> {
+            k(c, v)?;
+            let taken = c.builder().build_load(count, "count").into_int_value();
+            let one = c.context().i64_type().const_int(1, false);
+            let taken = c.builder().build_int_add(taken, one, "taken");
+            c.builder().build_store(count, taken);
+            let full = c
+                .builder()
+                .build_int_compare(IntPredicate::EQ, taken, n, "full");
+            let more_block = c.context().append_basic_block(&function, "limit_more");
+            c.builder()
+                .build_conditional_branch(full, &stop_block, &more_block);
+            c.builder().position_at_end(&more_block);
+            Ok(())
+        };
+        if paths {
+            f.generate_paths(self, val, &mut limited)?;
+        } else {
+            f.generate(self, val, &mut limited)?;
+        }
+        self.builder.build_unconditional_branch(&end_block);
+
+        self.builder.position_at_end(&stop_block);
+        let drop = self.get_function("jq_gen_drop")?;
+        self.builder.build_call(drop, &[mark], "drop");
+        self.builder.build_unconditional_branch(&end_block);
+        self.builder.position_at_end(&end_block);
+        Ok(())
+    }
+
+    /// Compiles a call to the builtin `name`.
+    fn generate_call(
+        &mut self,
+        name: &str,
+        args: &[Filter],
+        val: StructValue,
+        k: &mut Cont<Script>,
+    ) -> Result<(), CompilerError> {
+        match (name, args) {
+            ("empty", []) => Ok(()),
+            ("select", [cond]) => cond.generate(self, val, &mut |c: &mut Script, v: StructValue| {
+                c.build_branch(v, |c, truthy| if truthy { k(c, val) } else { Ok(()) })
+            }),
+            ("not", []) => {
+                let i64_type = self.context.i64_type();
+                let truthy = self.call("jq_truthy", &[val.into()])?.into_int_value();
+                let b = self.builder.build_int_compare(
+                    IntPredicate::EQ,
+                    truthy,
+                    i64_type.const_int(0, false),
+                    "not",
+                );
+                let b = self.builder.build_int_z_extend(b, i64_type, "b");
+                let b = self.call_json("jq_bool", &[b.into()])?;
+                k(self, b)
+            }
+            ("error", []) => {
+                let err = self.call_json("jq_error", &[val.into()])?;
+                self.check(err);
+                Ok(())
+            }
+            ("error", [msg]) => msg.generate(self, val, &mut |c: &mut Script, m: StructValue| {
+                let err = c.call_json("jq_error", &[m.into()])?;
+                c.check(err);
+                Ok(())
+            }),
+            ("limit", [n, f]) => n.generate(self, val, &mut |c: &mut Script, n: StructValue| {
+                let n = c.call("jq_limit", &[n.into()])?.into_int_value();
+                c.generate_limit(f, val, n, false, k)
+            }),
+            ("first", [f]) => {
+                let one = self.context.i64_type().const_int(1, false);
+                self.generate_limit(f, val, one, false, k)
+            }
+            ("path", [f]) => {
+                let outer = self.root.replace(val);
+                let root = self.const_json(Value::Array(Vec::new()));
+                f.generate_paths(self, root, &mut |c: &mut Script, p: StructValue| {
+                    // the paths are plain values to whatever consumes them
+                    let inner = std::mem::replace(&mut c.root, outer);
+                    k(c, p)?;
+                    c.root = inner;
+                    Ok(())
+                })?;
+                self.root = outer;
+                Ok(())
+            }
+            ("getpath", [p]) => p.generate(self, val, &mut |c: &mut Script, p: StructValue| {
+                let v = c.call_json("jq_getpath", &[val.into(), p.into()])?;
+                let v = c.check(v);
+                k(c, v)
+            }),
+            ("setpath", [p, x]) => generate_binop(self, p, x, val, k, |c, p, x| {
+                let v = c.call_json("jq_setpath", &[val.into(), p.into(), x.into()])?;
+                Ok(c.check(v))
+            }),
+            ("delpaths", [ps]) => ps.generate(self, val, &mut |c: &mut Script, ps: StructValue| {
+                let v = c.call_json("jq_delpaths", &[val.into(), ps.into()])?;
+                let v = c.check(v);
+                k(c, v)
+            }),
+            ("test", [re]) | ("match", [re]) | ("capture", [re]) | ("scan", [re])
+            | ("splits", [re]) => self.generate_match(name, re, None, val, k),
+            ("test", [re, flags])
+            | ("match", [re, flags])
+            | ("capture", [re, flags])
+            | ("scan", [re, flags])
+            | ("split", [re, flags])
+            | ("splits", [re, flags]) => self.generate_match(name, re, Some(flags), val, k),
+            ("sub", [re, repl]) | ("gsub", [re, repl]) => {
+                self.generate_sub(re, repl, None, name == "gsub", val, k)
+            }
+            ("sub", [re, repl, flags]) | ("gsub", [re, repl, flags]) => {
+                self.generate_sub(re, repl, Some(flags), name == "gsub", val, k)
+            }
+            ("strftime", [f])
+            | ("strflocaltime", [f])
+            | ("strptime", [f])
+            | ("ltrimstr", [f])
+            | ("rtrimstr", [f])
+            | ("startswith", [f])
+            | ("endswith", [f])
+            | ("split", [f])
+            | ("join", [f])
+            | ("indices", [f]) => {
+                let fun = format!("jq_{}", name);
+                f.generate(self, val, &mut |c: &mut Script, f: StructValue| {
+                    let v = c.call_json(&fun, &[val.into(), f.into()])?;
+                    let v = c.check(v);
+                    k(c, v)
+                })
+            }
+            (f, []) if is_math(&MATH_1, f) => {
+                let x = self.to_f64(val)?;
+                let r = self.call(f, &[x.into()])?;
+                let r = self.call_json("jq_from_f64", &[r])?;
+                k(self, r)
+            }
+            (f, [x, y]) if is_math(&MATH_2, f) => generate_binop(self, x, y, val, k, |c, x, y| {
+                let (x, y) = (c.to_f64(x)?, c.to_f64(y)?);
+                let r = c.call(f, &[x.into(), y.into()])?;
+                c.call_json("jq_from_f64", &[r])
+            }),
+            (f, [x, y, z]) if is_math(&MATH_3, f) => {
+                z.generate(self, val, &mut |c: &mut Script, z: StructValue| {
+                    generate_binop(c, x, y, val, k, |c, x, y| {
+                        let (x, y, z) = (c.to_f64(x)?, c.to_f64(y)?, c.to_f64(z)?);
+                        let r = c.call(f, &[x.into(), y.into(), z.into()])?;
+                        c.call_json("jq_from_f64", &[r])
+                    })
+                })
+            }
+            ("ldexp", [x, e])
+            | ("scalb", [x, e])
+            | ("scalbn", [x, e])
+            | ("scalbln", [x, e]) => {
+                generate_binop(self, x, e, val, k, |c, x, e| {
+                    let (x, e) = (c.to_f64(x)?, c.to_f64(e)?);
+                    let i64_type = c.context().i64_type();
+                    let e = c.builder().build_float_to_signed_int(e, i64_type, "e");
+                    let r = c.call("scalbln", &[x.into(), e.into()])?;
+                    c.call_json("jq_from_f64", &[r])
+                })
+            }
+            ("drem", [x, y]) => generate_binop(self, x, y, val, k, |c, x, y| {
+                let (x, y) = (c.to_f64(x)?, c.to_f64(y)?);
+                let r = c.call("jq_drem", &[x.into(), y.into()])?;
+                c.call_json("jq_from_f64", &[r])
+            }),
+            ("significand", []) => {
+                let x = self.to_f64(val)?;
+                let r = self.call("jq_significand", &[x.into()])?;
+                let r = self.call_json("jq_from_f64", &[r])?;
+                k(self, r)
+            }
+            ("frexp", []) | ("modf", []) => {
+                let x = self.to_f64(val)?;
+                let r = self.call_json(&format!("jq_{}", name), &[x.into()])?;
+                k(self, r)
+            }
+            ("isinfinite", []) | ("isnan", []) | ("isnormal", []) => {
+                let x = self.to_f64(val)?;
+                let b = self.call(&format!("jq_{}", name), &[x.into()])?;
+                let b = self.call_json("jq_bool", &[b])?;
+                k(self, b)
+            }
+            ("infinite", []) | ("nan", []) => {
+                let x = if name == "nan" {
+                    std::f64::NAN
+                } else {
+                    std::f64::INFINITY
+                };
+                let x = self.context.f64_type().const_float(x);
+                let r = self.call_json("jq_from_f64", &[x.into()])?;
+                k(self, r)
+            }
+            ("to_entries", [])
+            | ("from_entries", [])
+            | ("now", [])
+            | ("mktime", [])
+            | ("gmtime", [])
+            | ("localtime", [])
+            | ("length", [])
+            | ("utf8bytelength", [])
+            | ("ascii_downcase", [])
+            | ("ascii_upcase", [])
+            | ("trim", [])
+            | ("ltrim", [])
+            | ("rtrim", [])
+            | ("explode", [])
+            | ("implode", [])
+            | ("tostring", [])
+            | ("tojson", [])
+            | ("fromjson", [])
+            | ("tonumber", []) => {
+                let v = self.call_json(&format!("jq_{}", name), &[val.into()])?;
+                let v = self.check(v);
+                k(self, v)
+            }
+            _ => Err(CompilerError::UnknownFunction(format!(
+                "{}/{}",
+                name,
+                args.len()
+            ))),
+        }
+    }
+
+    /// The number `v` as a float, fails if it is no number.
+    fn to_f64(&mut self, v: StructValue) -> Result<FloatValue, CompilerError> {
+        let v = self.call_json("jq_number_check", &[v.into()])?;
+        let v = self.check(v);
+        Ok(self.call("jq_to_f64", &[v.into()])?.into_float_value())
+    }
+
+    /// Runs `body` with the regex `re` compiled with `flags`. Literals are
+    /// compiled once along with the script, otherwise `body` gets `0` and
+    /// every output of `re` and `flags` to compile at runtime.
+    fn generate_regex<F>(
+        &mut self,
+        re: &Filter,
+        flags: Option<&Filter>,
+        val: StructValue,
+        mut body: F,
+    ) -> Result<(), CompilerError>
+    where
+        F: FnMut(&mut Script, IntValue, StructValue, StructValue) -> Result<(), CompilerError>,
+    {
+        let i64_type = self.context.i64_type();
+        let null = Filter::Literal(Value::Null);
+        let flags = flags.unwrap_or(&null);
+        if let (Some(r), Some(f)) = (self.scope.constant(re), self.scope.constant(flags)) {
+            // invalid literals are left to fail at runtime
+            if let Ok(regex) = compile_regex(&r, &f) {
+                let regex = Box::new(regex);
+                let ptr = i64_type.const_int(&*regex as *const Regex as u64, false);
+                self.regexes.push(regex);
+                let (r, f) = (self.const_json(r), self.const_json(f));
+                return body(self, ptr, r, f);
+            }
+        }
+        let none = i64_type.const_int(0, false);
+        flags.generate(self, val, &mut |c: &mut Script, f: StructValue| {
+            re.generate(c, val, &mut |c: &mut Script, r: StructValue| body(c, none, r, f))
+        })
+    }
+
+    /// `test`, `match`, `capture`, `scan`, `split/2` and `splits`.
+    fn generate_match(
+        &mut self,
+        name: &str,
+        re: &Filter,
+        flags: Option<&Filter>,
+        val: StructValue,
+        k: &mut Cont<Script>,
+    ) -> Result<(), CompilerError> {
+        let mode = match name {
+            "test" => REGEX_TEST,
+            "match" => REGEX_MATCH,
+            "capture" => REGEX_CAPTURE,
+            "scan" => REGEX_SCAN,
+            _ => REGEX_SPLIT,
+        };
+        let mode = self.context.i64_type().const_int(mode as u64, false);
+        self.generate_regex(re, flags, val, |c, regex, re, flags| {
+            let args = [val.into(), regex.into(), re.into(), flags.into(), mode.into()];
+            let v = c.call_json("jq_match", &args)?;
+            let v = c.check(v);
+            match name {
+                "test" | "split" => k(c, v),
+                _ => {
+                    let len = c.call("jq_iter_len", &[v.into()])?.into_int_value();
+                    c.build_loop(len, |c, i| {
+                        let m = c.call_json("jq_iter_get", &[v.into(), i.into()])?;
+                        k(c, m)
+                    })
+                }
+            }
+        })
+    }
+
+    /// `sub` and `gsub`, `repl` runs on the named captures of every match
+    /// and its nth output goes into the nth result.
+    fn generate_sub(
+        &mut self,
+        re: &Filter,
+        repl: &Filter,
+        flags: Option<&Filter>,
+        global: bool,
+        val: StructValue,
+        k: &mut Cont<Script>,
+    ) -> Result<(), CompilerError> {
+        let mode = if global { REGEX_GLOBAL } else { REGEX_MATCH };
+        let mode = self.context.i64_type().const_int(mode as u64, false);
+        let json = self.json_struct().into();
+        let state = self.create_entry_block_alloca(json, "sub");
+        let push = self.get_function("jq_array_push")?;
+        self.generate_regex(re, flags, val, |c, regex, re, flags| {
+            let args = [val.into(), regex.into(), re.into(), flags.into(), mode.into()];
+            let matches = c.call_json("jq_match", &args)?;
+            let matches = c.check(matches);
+            let mut initial = Value::Object(Default::default());
+            if let Value::Object(o) = &mut initial {
+                o.insert("result".to_string(), Value::Array(Vec::new()));
+                o.insert("previous".to_string(), Value::I64(0));
+            }
+            let initial = c.const_json(initial);
+            c.builder().build_store(state, initial);
+            let len = c.call("jq_iter_len", &[matches.into()])?.into_int_value();
+            c.build_loop(len, |c, i| {
+                let m = c.call_json("jq_iter_get", &[matches.into(), i.into()])?;
+                let captures = c.call_json("jq_capture_object", &[m.into()])?;
+                let inserts = c.call_json("jq_array_new", &[])?;
+                repl.generate(c, captures, &mut |c: &mut Script, v: StructValue| {
+                    c.builder().build_call(push, &[inserts.into(), v.into()], "push");
+                    Ok(())
+                })?;
+                let s = c.builder().build_load(state, "state");
+                let s = c.call_json("jq_sub_step", &[s, val.into(), m.into(), inserts.into()])?;
+                let s = c.check(s);
+                c.builder().build_store(state, s);
+                Ok(())
+            })?;
+            let s = c.builder().build_load(state, "state");
+            let results = c.call_json("jq_sub_results", &[s, val.into()])?;
+            let len = c.call("jq_iter_len", &[results.into()])?.into_int_value();
+            c.build_loop(len, |c, i| {
+                let r = c.call_json("jq_iter_get", &[results.into(), i.into()])?;
+                k(c, r)
+            })
+        })
+    }
+
+    /// Path mode version of `generate_call`, builtins that don't select parts
+    /// of their input fail on their outputs.
+    fn generate_call_paths(
+        &mut self,
+        name: &str,
+        args: &[Filter],
+        path: StructValue,
+        k: &mut Cont<Script>,
+    ) -> Result<(), CompilerError> {
+        let val = self.path_value(path)?;
+        match (name, args) {
+            ("empty", []) | ("error", _) => self.generate_call(name, args, val, k),
+            ("select", [cond]) => cond.generate(self, val, &mut |c: &mut Script, v: StructValue| {
+                c.build_branch(v, |c, truthy| if truthy { k(c, path) } else { Ok(()) })
+            }),
+            ("limit", [n, f]) => n.generate(self, val, &mut |c: &mut Script, n: StructValue| {
+                let n = c.call("jq_limit", &[n.into()])?.into_int_value();
+                c.generate_limit(f, path, n, true, k)
+            }),
+            ("first", [f]) => {
+                let one = self.context.i64_type().const_int(1, false);
+                self.generate_limit(f, path, one, true, k)
+            }
+            ("getpath", [p]) => p.generate(self, val, &mut |c: &mut Script, p: StructValue| {
+                let v = c.call_json("jq_getpath", &[val.into(), p.into()])?;
+                c.check(v);
+                let p = c.call_json("jq_add", &[path.into(), p.into()])?;
+                let p = c.check(p);
+                k(c, p)
+            }),
+            _ => self.generate_call(name, args, val, &mut |c: &mut Script, v: StructValue| {
+                c.invalid_path(v)
+            }),
+        }
+    }
+
     pub fn jit_compile_main(&mut self, debug: bool) -> Result<JitFunction<MainFunc>, CompilerError> {
+        self.jit_compile("printd", debug)
+    }
+
+    /// Compiles the script as `main`, which hands every output to the
+    /// runtime function `emit`.
+    pub fn jit_compile(
+        &mut self,
+        emit: &str,
+        debug: bool,
+    ) -> Result<JitFunction<MainFunc>, CompilerError> {
         let ret_type = self.json_struct;
 
         let fn_type = ret_type.fn_type(&[self.json_struct().into()], false);
@@ -141,21 +2283,463 @@ impl Script {
 
         self.fn_value_opt = Some(function);
         let basic_block = self.context.append_basic_block(&function, "entry");
+        let fail_block = self.context.append_basic_block(&function, "error");
         self.builder.position_at_end(&basic_block);
+        let slot = self.create_entry_block_alloca(self.json_struct.into(), "error");
+        self.handler = Some(Handler {
+            block: fail_block.clone(),
+            slot,
+        });
 
-        let mut res = w;
-        for expr in &self.script {
-            res = expr.compile(self, res)?
-        }
+        // Every output is handed to `emit`, once the filter is exhausted
+        // main returns the (error free) input.
+        let emit = self.get_function(emit)?;
+        let script = self.script.clone();
+        script.generate(self, w, &mut |c: &mut Script, v: StructValue| {
+            c.builder().build_call(emit, &[v.into()], "emit");
+            Ok(())
+        })?;
+        self.builder.build_return(Some(&w));
 
-        self.builder.build_return(Some(&res));
+        self.builder.position_at_end(&fail_block);
+        let err = self.builder.build_load(slot, "err");
+        self.builder.build_return(Some(&err));
 
     if debug{
         self.module.print_to_stderr();}
-        unsafe {
+        let main = unsafe {
             self.execution_engine
                 .get_function("main")
-                .map_err(|_| CompilerError::Generic)
+                .map_err(|_| CompilerError::Generic)?
+        };
+        for (name, address) in &mut self.entries {
+            **address = self
+                .execution_engine
+                .get_function_address(name)
+                .map_err(|_| CompilerError::Generic)?;
         }
+        Ok(main)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::filter;
+    use crate::std_lib::{reset, with_prelude, JQType, Prototype};
+    use std::cell::RefCell;
+
+    thread_local! {
+        static OUTPUTS: RefCell<Vec<Value>> = RefCell::new(Vec::new());
+    }
+
+    #[used]
+    static E_TEST_EMIT: unsafe extern "C" fn(Wrap) = jq_test_emit;
+    #[no_mangle]
+    pub unsafe extern "C" fn jq_test_emit(w: Wrap) {
+        OUTPUTS.with(|o| o.borrow_mut().push((*w.json).clone()));
+    }
+
+    fn json(s: &str) -> Value {
+        simd_json::to_owned_value(&mut s.as_bytes().to_vec()).unwrap()
+    }
+
+    fn compile(script: &str) -> Result<(Script, JitFunction<MainFunc>), CompilerError> {
+        let (_, f) = filter(script).unwrap();
+        let mut jq = Script::from_filter(with_prelude(f));
+        let emit = Prototype {
+            name: "jq_test_emit",
+            args: &[("json", JQType::JSON)],
+            ret: JQType::Void,
+        };
+        emit.compile::<Script>(&jq)?;
+        let main = jq.jit_compile("jq_test_emit", false)?;
+        Ok((jq, main))
+    }
+
+    /// The outputs of `script` for `input`, or the error it stopped with.
+    fn run(script: &str, input: &str) -> Result<Vec<Value>, Value> {
+        let (_jq, main) = compile(script).unwrap();
+        let input = json(input);
+        let r = unsafe {
+            main.call(Wrap {
+                error: 0,
+                json: &input,
+            })
+        };
+        let outputs = OUTPUTS.with(|o| o.replace(Vec::new()));
+        let result = if r.error == 0 {
+            Ok(outputs)
+        } else {
+            Err(unsafe { (*r.json).clone() })
+        };
+        reset();
+        result
+    }
+
+    fn outputs(values: &[&str]) -> Result<Vec<Value>, Value> {
+        Ok(values.iter().map(|v| json(v)).collect())
+    }
+
+    #[test]
+    fn recursive_defs() {
+        let count = "def f: if . < 3 then ., (. + 1 | f) else . end; f";
+        assert_eq!(run(count, "0"), outputs(&["0", "1", "2", "3"]));
+        let fac = "def fac: if . <= 1 then 1 else . * (. - 1 | fac) end; fac";
+        assert_eq!(run(fac, "5"), outputs(&["120"]));
+        let down = "def f: if . > 0 then . - 1 | f else . end; f";
+        assert_eq!(run(down, "5000"), outputs(&["0"]));
+        let sum = "def f: if . > 0 then . + (. - 1 | f) else 0 end; f";
+        assert_eq!(run(sum, "3000"), outputs(&["4501500"]));
+        let args = "def f($n; $m): $n + $m; f(1; 2, 3)";
+        assert_eq!(run(args, "null"), outputs(&["3", "4"]));
+    }
+
+    #[test]
+    fn recursion_growing_closures() {
+        let double = "def f(g): if . > 0 then . - 1 | f(g | g) else g end; reduce (3 | f(1, 2)) as $x (0; . + 1)";
+        assert_eq!(run(double, "null"), outputs(&["256"]));
+        let times = "def f(g): if . > 2 then g else . + 1 | f(g | . * 2) end; f(.)";
+        assert_eq!(run(times, "0"), outputs(&["12"]));
+    }
+
+    #[test]
+    fn limit_and_first_stop_generators() {
+        let r = "def r: ., (. + 1 | r); [limit(3; r | . * 10)]";
+        assert_eq!(run(r, "1"), outputs(&["[10, 20, 30]"]));
+        assert_eq!(run("def r: ., r; first(r)", "1"), outputs(&["1"]));
+        assert_eq!(run("[limit(2; .[])]", "[1, 2, 3]"), outputs(&["[1, 2]"]));
+        assert_eq!(run("[limit(0; error(1))]", "null"), outputs(&["[]"]));
+        assert_eq!(run("[limit(-1; 1, 2)]", "null"), outputs(&["[1, 2]"]));
+        assert_eq!(run("[first(.[])]", "[]"), outputs(&["[]"]));
+        assert_eq!(run("[limit(3; repeat(.))]", "1"), outputs(&["[1, 1, 1]"]));
+        assert_eq!(
+            run("[limit(3; repeat(.) | . + 1)]", "1"),
+            outputs(&["[2, 2, 2]"])
+        );
+        assert_eq!(
+            run("[limit(3; repeat(. * 2))]", "1"),
+            outputs(&["[1, 2, 4]"])
+        );
+    }
+
+    #[test]
+    fn consumer_errors_skip_the_generators_handlers() {
+        let script = "def f: try (1, 2) catch 0; try (f | error) catch .";
+        assert_eq!(run(script, "null"), outputs(&["1"]));
+    }
+
+    #[test]
+    fn runaway_recursion_fails() {
+        assert_eq!(
+            run("def r: r; r", "null"),
+            Err(json(r#""r/0 recursed too deeply""#))
+        );
+    }
+
+    #[test]
+    fn recursive_descent() {
+        let input = r#"{"a": [1, {"b": 2}]}"#;
+        assert_eq!(
+            run("[..]", input),
+            outputs(&[r#"[{"a": [1, {"b": 2}]}, [1, {"b": 2}], 1, {"b": 2}, 2]"#])
+        );
+        assert_eq!(run("[.. | select(. < [])]", input), outputs(&["[1, 2]"]));
+        assert_eq!(
+            run("[recurse(if . < 3 then . + 1 else empty end)]", "0"),
+            outputs(&["[0, 1, 2, 3]"])
+        );
+        assert_eq!(
+            run("[recurse(. * 2; . < 10)]", "1"),
+            outputs(&["[1, 2, 4, 8]"])
+        );
+        let deep = format!("{}{}", "[".repeat(3000), "]".repeat(3000));
+        assert_eq!(run("reduce .. as $x (0; . + 1)", &deep), outputs(&["3000"]));
+        assert_eq!(
+            run("reduce recurse as $x (0; . + 1)", &deep),
+            outputs(&["3000"])
+        );
+    }
+
+    #[test]
+    fn alternative_operator() {
+        assert_eq!(run(r#".a // "d""#, "{}"), outputs(&[r#""d""#]));
+        assert_eq!(
+            run("(false, null, 1, 2) // 3", "null"),
+            outputs(&["1", "2"])
+        );
+        assert_eq!(run("(false, null) // 3", "null"), outputs(&["3"]));
+        assert_eq!(run("empty // 3", "null"), outputs(&["3"]));
+        assert_eq!(run("(1, error(0)) // 3", "null"), outputs(&["1"]));
+        assert_eq!(run(r#"error("x") // 3"#, "null"), outputs(&["3"]));
+        assert_eq!(run(".[] // 0", "[null, 1, false, 2]"), outputs(&["1", "2"]));
+    }
+
+    #[test]
+    fn string_interpolation() {
+        assert_eq!(
+            run(r#""x\(.a)y\(.b)""#, r#"{"a": [1], "b": "s"}"#),
+            outputs(&[r#""x[1]ys""#])
+        );
+        assert_eq!(
+            run(r#"@base64 "v=\(.)""#, r#""hi""#),
+            outputs(&[r#""v=aGk=""#])
+        );
+        assert_eq!(
+            run(r#"@json "v=\(.)""#, r#""hi""#),
+            outputs(&[r#""v=\"hi\"""#])
+        );
+        assert_eq!(run("@csv", "[1, 2]"), outputs(&[r#""1,2""#]));
+        assert!(run("@csv", "1").is_err());
+    }
+
+    #[test]
+    fn assignment_operators() {
+        let input = r#"{"a": 1, "b": [1, 2]}"#;
+        assert_eq!(
+            run(".a = .b", input),
+            outputs(&[r#"{"a": [1, 2], "b": [1, 2]}"#])
+        );
+        assert_eq!(run(".a = (1, 2) | .a", input), outputs(&["1", "2"]));
+        assert_eq!(
+            run(".b[] |= . * 10", input),
+            outputs(&[r#"{"a": 1, "b": [10, 20]}"#])
+        );
+        assert_eq!(run(".a |= empty", input), outputs(&[r#"{"b": [1, 2]}"#]));
+        assert_eq!(
+            run(".b[] += .a", input),
+            outputs(&[r#"{"a": 1, "b": [2, 3]}"#])
+        );
+        assert_eq!(run(".a -= 1 | .a", input), outputs(&["0"]));
+        assert_eq!(run(".c //= 3 | .c", input), outputs(&["3"]));
+        assert_eq!(run(".a //= 3 | .a", input), outputs(&["1"]));
+        assert_eq!(run(".x.y = 1", "null"), outputs(&[r#"{"x": {"y": 1}}"#]));
+        assert_eq!(
+            run("1 = 2", "null"),
+            Err(json(r#""Invalid path expression with result 1""#))
+        );
+    }
+
+    #[test]
+    fn defs_in_path_mode() {
+        assert_eq!(
+            run("(.. | select(. == 1)) |= 2", "[1, [1, 0]]"),
+            outputs(&["[2, [2, 0]]"])
+        );
+        assert_eq!(
+            run("first(.a, .b) = 0", r#"{"a": 1, "b": 2}"#),
+            outputs(&[r#"{"a": 0, "b": 2}"#])
+        );
+        assert_eq!(
+            run("limit(1; .[]) |= . + 1", "[1, 2]"),
+            outputs(&["[2, 2]"])
+        );
+        let deepest = "def f(p): if (p | .a) == null then p else f(p | .a) end; f(.) |= 0";
+        assert_eq!(
+            run(deepest, r#"{"a": {"a": {}}}"#),
+            outputs(&[r#"{"a": {"a": 0}}"#])
+        );
+    }
+
+    #[test]
+    fn path_builtins() {
+        let input = r#"{"a": [1, {"b": null}]}"#;
+        assert_eq!(
+            run("[path(..)]", input),
+            outputs(&[r#"[[], ["a"], ["a", 0], ["a", 1], ["a", 1, "b"]]"#])
+        );
+        assert_eq!(
+            run("[path(.a[1].b?)]", input),
+            outputs(&[r#"[["a", 1, "b"]]"#])
+        );
+        assert_eq!(
+            run("[leaf_paths]", input),
+            outputs(&[r#"[["a", 0], ["a", 1, "b"]]"#])
+        );
+        assert_eq!(
+            run("[paths(. == null)]", input),
+            outputs(&[r#"[["a", 1, "b"]]"#])
+        );
+        assert_eq!(
+            run(r#"getpath(["a", 1, "b", "c"])"#, input),
+            outputs(&["null"])
+        );
+        assert_eq!(
+            run(r#"setpath(["a", 0]; 2) | .a"#, input),
+            outputs(&[r#"[2, {"b": null}]"#])
+        );
+        assert_eq!(
+            run(r#"delpaths([["a", 0], ["a", 1]])"#, input),
+            outputs(&[r#"{"a": []}"#])
+        );
+        assert_eq!(run("del(.a[0, 1])", input), outputs(&[r#"{"a": []}"#]));
+        assert_eq!(
+            run(r#"path(getpath(["x", 0]))"#, "null"),
+            outputs(&[r#"["x", 0]"#])
+        );
+        assert_eq!(
+            run("with_entries(.value += 1)", r#"{"a": 1}"#),
+            outputs(&[r#"{"a": 2}"#])
+        );
+        assert_eq!(
+            run("path(1)", "null"),
+            Err(json(r#""Invalid path expression with result 1""#))
+        );
+        assert_eq!(run("path(.a // .b)", r#"{"b": 1}"#), outputs(&[r#"["b"]"#]));
+        assert_eq!(
+            run("[path(first(.a, .b))]", "null"),
+            outputs(&[r#"[["a"]]"#])
+        );
+    }
+
+    #[test]
+    fn paths_of_deep_values() {
+        let deep = format!("{}{}", "[".repeat(3000), "]".repeat(3000));
+        assert_eq!(
+            run("reduce paths as $p (0; . + 1)", &deep),
+            outputs(&["2999"])
+        );
+        let leaf = format!("{}1{}", "[".repeat(3000), "]".repeat(3000));
+        assert_eq!(
+            run("reduce leaf_paths as $p (0; . + 1)", &leaf),
+            outputs(&["1"])
+        );
+    }
+
+    #[test]
+    fn regex_builtins() {
+        let input = r#""a1 b22""#;
+        assert_eq!(run(r#"test("B"; "i")"#, input), outputs(&["true"]));
+        assert_eq!(
+            run(r#". as $re | "xay" | test($re)"#, r#""a""#),
+            outputs(&["true"])
+        );
+        assert_eq!(
+            run(r#"[match("\\d+"; "g") | .offset]"#, input),
+            outputs(&["[1, 4]"])
+        );
+        assert_eq!(
+            run(r#"capture("(?<l>[a-z])(?<n>\\d+)")"#, input),
+            outputs(&[r#"{"l": "a", "n": "1"}"#])
+        );
+        assert_eq!(
+            run(r#"[scan("\\d+")]"#, input),
+            outputs(&[r#"["1", "22"]"#])
+        );
+        assert_eq!(
+            run(r#"split("\\d+"; null)"#, input),
+            outputs(&[r#"["a", " b", ""]"#])
+        );
+        assert_eq!(
+            run(r#"[splits(" ")]"#, input),
+            outputs(&[r#"["a1", "b22"]"#])
+        );
+        assert_eq!(
+            run(r#"sub("(?<n>\\d+)"; "<\(.n)>")"#, input),
+            outputs(&[r#""a<1> b22""#])
+        );
+        assert_eq!(
+            run(r##"gsub("\\d"; "#")"##, input),
+            outputs(&[r##""a# b##""##])
+        );
+        assert_eq!(
+            run(r#"[sub("\\d"; "x", "y")]"#, input),
+            outputs(&[r#"["ax b22", "ay b22"]"#])
+        );
+        assert_eq!(run(r#"sub("z"; "y")"#, input), outputs(&[input]));
+        assert_eq!(
+            run(r#"test("a")"#, "1"),
+            Err(json(
+                r#""number (1) cannot be matched, as it is not a string""#
+            ))
+        );
+    }
+
+    #[test]
+    fn date_builtins() {
+        let date = r#""2015-03-05T23:51:47Z""#;
+        assert_eq!(run("todate", "1425599507"), outputs(&[date]));
+        assert_eq!(run("fromdate", date), outputs(&["1425599507"]));
+        assert_eq!(
+            run(r#"strptime("%Y-%m-%dT%H:%M:%SZ") | mktime"#, date),
+            outputs(&["1425599507"])
+        );
+        assert_eq!(run("gmtime | todate", "1425599507"), outputs(&[date]));
+        assert_eq!(
+            run(r#"dateadd("seconds"; 13) | todate"#, "1425599494"),
+            outputs(&[date])
+        );
+        assert_eq!(run("now > 1425599507", "null"), outputs(&["true"]));
+    }
+
+    #[test]
+    fn non_finite_numbers() {
+        assert_eq!(
+            run("[infinite, -infinite, nan] | @text", "null"),
+            outputs(&[r#""[1.7976931348623157e+308,-1.7976931348623157e+308,null]""#])
+        );
+        assert_eq!(
+            run("infinite | @text", "null"),
+            outputs(&[r#""1.7976931348623157e+308""#])
+        );
+        assert_eq!(
+            run(
+                "[nan | isnan, (infinite | isinfinite), (1 | isnormal)]",
+                "null"
+            ),
+            outputs(&["[true, true, true]"])
+        );
+        assert_eq!(
+            run("[.[] | floor, sqrt]", "[4.5]"),
+            outputs(&["[4, 2.1213203435596424]"])
+        );
+        assert_eq!(run("pow(2; 10)", "null"), outputs(&["1024"]));
+        assert_eq!(
+            run(
+                "[(3 | gamma | . * 1000 | round), (2 | exp10, pow10, significand)]",
+                "null"
+            ),
+            outputs(&["[693, 100, 100, 1]"])
+        );
+        assert_eq!(
+            run("[drem(7; 2), scalb(3; 2), ldexp(3; 2)]", "null"),
+            outputs(&["[-1, 12, 12]"])
+        );
+    }
+
+    #[test]
+    fn string_builtins() {
+        assert_eq!(
+            run(r#"[.[] | ltrimstr("a") | ascii_upcase]"#, r#"["ab", "c"]"#),
+            outputs(&[r#"["B", "C"]"#])
+        );
+        assert_eq!(
+            run(r#"split(", ") | join("-")"#, r#""a, b, c""#),
+            outputs(&[r#""a-b-c""#])
+        );
+        assert_eq!(
+            run(r#""" | split(1)"#, "null"),
+            Err(json(r#""split input and separator must be strings""#))
+        );
+        assert_eq!(
+            run(r#"[index(","), rindex(",")]"#, r#""a,b,c""#),
+            outputs(&["[1, 3]"])
+        );
+        assert_eq!(
+            run("[explode | .[] + 1] | implode", r#""abc""#),
+            outputs(&[r#""bcd""#])
+        );
+        assert_eq!(
+            run("implode", "[1114112]"),
+            Err(json(r#""Invalid codepoint literal""#))
+        );
+        assert_eq!(
+            run(r#"[.[] | tostring | tonumber]"#, r#"[1, "2"]"#),
+            outputs(&["[1, 2]"])
+        );
+        assert_eq!(
+            run("[infinite, -infinite, nan] | tojson", "null"),
+            outputs(&[r#""[1.7976931348623157e+308,-1.7976931348623157e+308,null]""#])
+        );
     }
 }
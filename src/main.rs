@@ -18,7 +18,7 @@ use std::io::prelude::*;
 
 #[no_mangle]
 pub extern "C" fn printd(w: Wrap) {
-    println!("{:?}", unsafe { &*w.json });
+    println!("{}", dump(unsafe { &*w.json }));
 }
 
 // Adding the functions above to a global array,
@@ -48,41 +48,44 @@ fn main() -> Result<(), Box<Error>> {
 
     let input = matches.value_of("INPUT").unwrap();
     let debug = matches.is_present("debug");
-    let (_, p) = path(input).unwrap();
-    let mut jq = Script::from_path(p);
+    let f = match program(input) {
+        Ok(f) => f,
+        Err(msg) => {
+            eprintln!("jq: error: {}\n{}\njq: 1 compile error", msg, input);
+            std::process::exit(3);
+        }
+    };
+    let mut jq = Script::from_filter(with_prelude(f));
     let jqs = jq.jit_compile_main(debug)?;
 
-    for l in io::stdin().lock().lines() {
-    unsafe {
-        let mut l = l.unwrap();
-    let mut json: Value = simd_json::to_owned_value(l.as_bytes_mut()).unwrap();
-    let wrap = Wrap {
-        error: 0,
-        json: &json,
-    };
-        let r = jqs.call(wrap);
-        if r.error == 0 {
-            println!("{}", (&*r.json).to_string())
-        } else {
-            println!("Error: {}", r.error)
+    for (n, l) in io::stdin().lock().lines().enumerate() {
+        unsafe {
+            let mut l = l.unwrap();
+            if l.trim().is_empty() {
+                continue;
+            }
+            let json: Value = match simd_json::to_owned_value(l.as_bytes_mut()) {
+                Ok(json) => json,
+                Err(e) => {
+                    eprintln!("jq: parse error: {} at line {}", e, n + 1);
+                    continue;
+                }
+            };
+            let wrap = Wrap {
+                error: 0,
+                json: &json,
+            };
+            // outputs are printed by `printd` as they are produced, an input
+            // the filter drops prints nothing
+            let r = jqs.call(wrap);
+            if r.error != 0 {
+                match &*r.json {
+                    Value::String(msg) => eprintln!("jq: error (at <stdin>:{}): {}", n + 1, msg),
+                    v => eprintln!("jq: error (at <stdin>:{}) (not a string): {}", n + 1, dump(v)),
+                }
+            }
+            std_lib::reset();
         }
     }
-    }
-
-    /*
-    let script = "let a = 3; let a = 4 + a; a * 6";
-    let (r, ast) = exprs(script).expect("Unable build ast");
-    dbg!(r);
-    dbg!(&ast);
-
-    let mut c = MathCompiler::new();
-    let fun = c.jit_compile_expr_root(&ast)?;
-    dbg!(unsafe { fun.call(wrap) });
-    println!("Hello, world!");
-    let _ = dbg!(path("."));
-    let _ = dbg!(path(".[7]"));
-    let _ = dbg!(path(".bla"));
-    let _ = dbg!(path(".bla.blubb[7]"));
-    */
     Ok(())
 }
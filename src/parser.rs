@@ -1,25 +1,62 @@
 use crate::expr::*;
 use crate::jq::*;
+use simd_json::OwnedValue as Value;
 use std::str::FromStr;
 
 
 
 use nom::{
     branch::alt,
-    bytes::complete::{tag, take_while1},
-    character::complete::{digit1 as digit, multispace0 as multispace},
-    combinator::{map, map_res},
-    multi::{many0, many1, separated_list},
-    sequence::{delimited, preceded},
+    bytes::complete::{tag, take_while, take_while1, take_while_m_n},
+    character::complete::{digit1 as digit, multispace0 as multispace, none_of, one_of},
+    combinator::{map, map_opt, map_res, not, opt, recognize, verify},
+    multi::{fold_many0, many0, many1, separated_list, separated_nonempty_list},
+    sequence::{delimited, pair, preceded, terminated, tuple},
     IResult,
 };
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Oper {
     Add,
     Sub,
     Mul,
     Div,
+    Mod,
+}
+
+/// An AST built by the operator grammar of `term` and `math_expr`, which
+/// math expressions and jq filters share.
+trait Operand: Sized {
+    /// An operand of `*`, `/` and `%`.
+    fn factor(i: &str) -> IResult<&str, Self>;
+    fn binop(oper: Oper, left: Self, right: Self) -> Self;
+}
+
+impl Operand for Expr {
+    fn factor(i: &str) -> IResult<&str, Expr> {
+        factor(i)
+    }
+
+    fn binop(oper: Oper, left: Expr, right: Expr) -> Expr {
+        let (left, right) = (Box::new(left), Box::new(right));
+        match oper {
+            Oper::Add => Expr::Add(AddExpr { left, right }),
+            Oper::Sub => Expr::Sub(SubExpr { left, right }),
+            Oper::Mul => Expr::Mul(MulExpr { left, right }),
+            Oper::Div => Expr::Div(DivExpr { left, right }),
+            Oper::Mod => Expr::Mod(ModExpr { left, right }),
+        }
+    }
+}
+
+impl Operand for Filter {
+    fn factor(i: &str) -> IResult<&str, Filter> {
+        arith_factor(i)
+    }
+
+    fn binop(oper: Oper, left: Filter, right: Filter) -> Filter {
+        Filter::Arith(oper, Box::new(left), Box::new(right))
+    }
 }
 
 fn parens(i: &str) -> IResult<&str, Expr> {
@@ -27,7 +64,7 @@ fn parens(i: &str) -> IResult<&str, Expr> {
         multispace,
         delimited(
             tag("("),
-            map(math_expr, |e| Expr::Paren(Box::new(e))),
+            map(math_expr::<Expr>, |e| Expr::Paren(Box::new(e))),
             tag(")"),
         ),
         multispace,
@@ -45,58 +82,110 @@ fn factor(i: &str) -> IResult<&str, Expr> {
     ))(i)
 }
 
-fn fold_exprs(initial: Expr, remainder: Vec<(Oper, Expr)>) -> Expr {
-    remainder.into_iter().fold(initial, |acc, pair| {
-        let (oper, expr) = pair;
-        match oper {
-            Oper::Add => Expr::Add(AddExpr {
-                left: Box::new(acc),
-                right: Box::new(expr),
-            }),
-            Oper::Sub => Expr::Sub(SubExpr {
-                left: Box::new(acc),
-                right: Box::new(expr),
-            }),
-            Oper::Mul => Expr::Mul(MulExpr {
-                left: Box::new(acc),
-                right: Box::new(expr),
-            }),
-            Oper::Div => Expr::Div(DivExpr {
-                left: Box::new(acc),
-                right: Box::new(expr),
-            }),
-        }
-    })
+fn fold_exprs<T: Operand>(initial: T, remainder: Vec<(Oper, T)>) -> T {
+    remainder
+        .into_iter()
+        .fold(initial, |acc, (oper, expr)| T::binop(oper, acc, expr))
 }
 
-fn term(i: &str) -> IResult<&str, Expr> {
-    let (i, initial) = factor(i)?;
+fn term<T: Operand>(i: &str) -> IResult<&str, T> {
+    let (i, initial) = T::factor(i)?;
     let (i, remainder) = many0(alt((
         |i| {
-            let (i, mul) = preceded(tag("*"), factor)(i)?;
+            let (i, mul) = preceded(arith_op("*"), T::factor)(i)?;
             Ok((i, (Oper::Mul, mul)))
         },
         |i| {
-            let (i, div) = preceded(tag("/"), factor)(i)?;
+            let (i, div) = preceded(arith_op("/"), T::factor)(i)?;
             Ok((i, (Oper::Div, div)))
         },
+        |i| {
+            let (i, rem) = preceded(arith_op("%"), T::factor)(i)?;
+            Ok((i, (Oper::Mod, rem)))
+        },
     )))(i)?;
 
     Ok((i, fold_exprs(initial, remainder)))
 }
 
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
 fn ident(i: &str) -> IResult<&str, String> {
-    map(take_while1(move |c| c >= 'a' && c <= 'z'), String::from)(i)
+    map(
+        recognize(preceded(
+            take_while_m_n(1, 1, is_ident_start),
+            take_while(is_ident_char),
+        )),
+        String::from,
+    )(i)
+}
+
+fn hex4(i: &str) -> IResult<&str, u32> {
+    map_res(take_while_m_n(4, 4, |c: char| c.is_ascii_hexdigit()), |h| {
+        u32::from_str_radix(h, 16)
+    })(i)
 }
-fn math_expr(i: &str) -> IResult<&str, Expr> {
+
+/// The code point of a `\uXXXX` escape, combining UTF-16 surrogate pairs.
+fn unicode_escape(i: &str) -> IResult<&str, u32> {
+    let (i, hi) = preceded(tag("u"), hex4)(i)?;
+    if (0xD800..0xDC00).contains(&hi) {
+        let (i, lo) = verify(preceded(tag("\\u"), hex4), |lo: &u32| {
+            (0xDC00..0xE000).contains(lo)
+        })(i)?;
+        Ok((i, 0x10000 + ((hi - 0xD800) << 10) + (lo - 0xDC00)))
+    } else {
+        Ok((i, hi))
+    }
+}
+
+fn escaped_char(i: &str) -> IResult<&str, char> {
+    preceded(
+        tag("\\"),
+        alt((
+            map_opt(unicode_escape, std::char::from_u32),
+            map(tag("\""), |_| '"'),
+            map(tag("\\"), |_| '\\'),
+            map(tag("/"), |_| '/'),
+            map(tag("b"), |_| '\u{8}'),
+            map(tag("f"), |_| '\u{c}'),
+            map(tag("n"), |_| '\n'),
+            map(tag("r"), |_| '\r'),
+            map(tag("t"), |_| '\t'),
+        )),
+    )(i)
+}
+
+/// A JSON string literal.
+fn string_lit(i: &str) -> IResult<&str, String> {
+    delimited(
+        tag("\""),
+        fold_many0(
+            alt((escaped_char, none_of("\"\\"))),
+            String::new(),
+            |mut s, c| {
+                s.push(c);
+                s
+            },
+        ),
+        tag("\""),
+    )(i)
+}
+fn math_expr<T: Operand>(i: &str) -> IResult<&str, T> {
     let (i, initial) = term(i)?;
     let (i, remainder) = many0(alt((
         |i| {
-            let (i, add) = preceded(tag("+"), term)(i)?;
+            let (i, add) = preceded(arith_op("+"), term)(i)?;
             Ok((i, (Oper::Add, add)))
         },
         |i| {
-            let (i, sub) = preceded(tag("-"), term)(i)?;
+            let (i, sub) = preceded(arith_op("-"), term)(i)?;
             Ok((i, (Oper::Sub, sub)))
         },
     )))(i)?;
@@ -105,7 +194,7 @@ fn math_expr(i: &str) -> IResult<&str, Expr> {
 
 fn let_expr(i: &str) -> IResult<&str, Expr> {
     let (i, name) = preceded(tag("let"), delimited(multispace, ident, multispace))(i)?;
-    let (i, expr) = preceded(tag("="), math_expr)(i)?;
+    let (i, expr) = preceded(tag("="), math_expr::<Expr>)(i)?;
     Ok((i, Expr::Let(name, Box::new(expr))))
 }
 
@@ -113,7 +202,7 @@ fn var_expr(i: &str) -> IResult<&str, Expr> {
     map(delimited(multispace, ident, multispace), Expr::Var)(i)
 }
 fn expr(i: &str) -> IResult<&str, Expr> {
-    alt((let_expr, math_expr))(i)
+    alt((let_expr, math_expr::<Expr>))(i)
 }
 
 fn dl(i: &str) -> IResult<&str, &str> {
@@ -125,27 +214,59 @@ pub fn exprs(i: &str) -> IResult<&str, Vec<Expr>> {
 }
 
 fn path_key(i: &str) -> IResult<&str, Path> {
-    map(ident, Path::Key)(i)
+    map(alt((ident, string_lit)), Path::Key)(i)
 }
 
-fn path_idx(i: &str) -> IResult<&str, Path> {
+fn path_str(i: &str) -> IResult<&str, Path> {
     map(
-        map_res(
-            delimited(tag("["), delimited(multispace, digit, multispace), tag("]")),
-            FromStr::from_str,
+        delimited(
+            tag("["),
+            delimited(multispace, string_lit, multispace),
+            tag("]"),
         ),
-        Path::Idx,
+        Path::Key,
     )(i)
 }
 
+fn int(i: &str) -> IResult<&str, i64> {
+    map_res(
+        delimited(
+            multispace,
+            recognize(preceded(opt(tag("-")), digit)),
+            multispace,
+        ),
+        FromStr::from_str,
+    )(i)
+}
+
+fn path_idx(i: &str) -> IResult<&str, Path> {
+    map(delimited(tag("["), int, tag("]")), Path::Idx)(i)
+}
+
+fn path_slice(i: &str) -> IResult<&str, Path> {
+    let (i, from) = preceded(tag("["), opt(int))(i)?;
+    let (i, to) = delimited(tag(":"), opt(int), tag("]"))(i)?;
+    Ok((i, Path::Slice(from, to)))
+}
+
+fn path_iter(i: &str) -> IResult<&str, Path> {
+    map(delimited(tag("["), multispace, tag("]")), |_| Path::Iter)(i)
+}
+
 fn path_seg(i: &str) -> IResult<&str, Path> {
-    alt((preceded(tag("."), path_key), path_idx))(i)
+    alt((
+        preceded(tag("."), path_key),
+        path_idx,
+        path_slice,
+        path_str,
+        path_iter,
+    ))(i)
 }
 pub fn path(i: &str) -> IResult<&str, Vec<Path>> {
     let (mut i, _) = tag(".")(i)?;
     let mut path = vec![Path::Root];
 
-    match alt((path_key, path_idx))(i) {
+    match alt((path_key, path_idx, path_slice, path_str, path_iter))(i) {
         Ok((i1, s)) => {
             path.push(s);
             i = i1
@@ -156,3 +277,1062 @@ pub fn path(i: &str) -> IResult<&str, Vec<Path>> {
     path.append(&mut ps);
     Ok((i, path))
 }
+
+const KEYWORDS: &[&str] = &[
+    "try", "catch", "and", "or", "if", "then", "elif", "else", "end", "as",
+    "reduce", "foreach", "def",
+];
+
+fn keyword<'a>(kw: &'static str) -> impl Fn(&'a str) -> IResult<&'a str, &'a str> {
+    delimited(
+        multispace,
+        terminated(tag(kw), not(take_while1(is_ident_char))),
+        multispace,
+    )
+}
+
+enum Chunk {
+    Char(char),
+    Interp(Filter),
+}
+
+/// A string literal that may contain interpolations like `"a\(.b)c"`.
+fn string_parts(i: &str) -> IResult<&str, Vec<StringPart>> {
+    delimited(
+        tag("\""),
+        fold_many0(
+            alt((
+                map(delimited(tag("\\("), filter, tag(")")), Chunk::Interp),
+                map(alt((escaped_char, none_of("\"\\"))), Chunk::Char),
+            )),
+            Vec::new(),
+            |mut parts: Vec<StringPart>, chunk| {
+                match (chunk, parts.last_mut()) {
+                    (Chunk::Char(c), Some(StringPart::Lit(s))) => s.push(c),
+                    (Chunk::Char(c), _) => parts.push(StringPart::Lit(c.to_string())),
+                    (Chunk::Interp(f), _) => parts.push(StringPart::Interp(f)),
+                }
+                parts
+            },
+        ),
+        tag("\""),
+    )(i)
+}
+
+/// A string with the interpolations formatted with `@format`, a plain
+/// literal when there are none.
+fn interpolated(format: String, parts: Vec<StringPart>) -> Filter {
+    // `string_parts` merges adjacent characters, so a literal is one part
+    match parts.as_slice() {
+        [] => Filter::Literal(Value::String(String::new())),
+        [StringPart::Lit(s)] => Filter::Literal(Value::String(s.clone())),
+        _ => Filter::Interpolate(format, parts),
+    }
+}
+
+fn string_term(i: &str) -> IResult<&str, Filter> {
+    map(string_parts, |parts| interpolated("text".to_string(), parts))(i)
+}
+
+/// `@name` on its own or followed by a string it formats the
+/// interpolations of.
+fn format(i: &str) -> IResult<&str, Filter> {
+    let (i, name) = preceded(tag("@"), ident)(i)?;
+    let (i, parts) = opt(preceded(multispace, string_parts))(i)?;
+    Ok((
+        i,
+        match parts {
+            Some(parts) => interpolated(name, parts),
+            None => Filter::Format(name),
+        },
+    ))
+}
+
+fn number(i: &str) -> IResult<&str, Value> {
+    let (i, n) = recognize(tuple((
+        opt(tag("-")),
+        digit,
+        opt(preceded(tag("."), digit)),
+        opt(tuple((one_of("eE"), opt(one_of("+-")), digit))),
+    )))(i)?;
+    let n = match i64::from_str(n) {
+        Ok(n) => Value::I64(n),
+        // the grammar above only accepts valid floats
+        Err(_) => Value::F64(f64::from_str(n).unwrap()),
+    };
+    Ok((i, n))
+}
+
+fn literal(i: &str) -> IResult<&str, Filter> {
+    map(
+        alt((
+            number,
+            map(keyword("true"), |_| Value::Bool(true)),
+            map(keyword("false"), |_| Value::Bool(false)),
+            map(keyword("null"), |_| Value::Null),
+        )),
+        Filter::Literal,
+    )(i)
+}
+
+fn var(i: &str) -> IResult<&str, String> {
+    preceded(tag("$"), ident)(i)
+}
+
+/// The value of an object entry, a pipeline that doesn't use `,`.
+fn object_value(i: &str) -> IResult<&str, Filter> {
+    let (i, initial) = filter_term(i)?;
+    let (i, remainder) = many0(preceded(tag("|"), filter_term))(i)?;
+    Ok((i, fold_pipe(initial, remainder)))
+}
+
+fn object_entry(i: &str) -> IResult<&str, (Filter, Filter)> {
+    if let Ok((i, name)) = var(i) {
+        return match preceded(delimited(multispace, tag(":"), multispace), object_value)(i) {
+            Ok((i, v)) => Ok((i, (Filter::Var(name), v))),
+            Err(_) => Ok((i, (Filter::Literal(Value::String(name.clone())), Filter::Var(name)))),
+        };
+    }
+    let (i, key) = alt((
+        map(ident, |k| Filter::Literal(Value::String(k))),
+        string_term,
+        filter_parens,
+    ))(i)?;
+    let (i, v) = opt(preceded(
+        delimited(multispace, tag(":"), multispace),
+        object_value,
+    ))(i)?;
+    match (key, v) {
+        (key, Some(v)) => Ok((i, (key, v))),
+        (Filter::Literal(Value::String(k)), None) => {
+            let v = Filter::Path(vec![Path::Root, Path::Key(k.clone())]);
+            Ok((i, (Filter::Literal(Value::String(k)), v)))
+        }
+        (_, None) => Err(nom::Err::Error((i, nom::error::ErrorKind::Char))),
+    }
+}
+
+fn array(i: &str) -> IResult<&str, Filter> {
+    map(
+        delimited(tag("["), delimited(multispace, opt(filter), multispace), tag("]")),
+        |f| Filter::Array(f.map(Box::new)),
+    )(i)
+}
+
+fn object(i: &str) -> IResult<&str, Filter> {
+    map(
+        delimited(
+            tag("{"),
+            separated_list(tag(","), delimited(multispace, object_entry, multispace)),
+            tag("}"),
+        ),
+        Filter::Object,
+    )(i)
+}
+
+fn filter_parens(i: &str) -> IResult<&str, Filter> {
+    delimited(tag("("), filter, tag(")"))(i)
+}
+
+fn call(i: &str) -> IResult<&str, Filter> {
+    let (i, name) = verify(ident, |s: &str| !KEYWORDS.contains(&s))(i)?;
+    let (i, args) = opt(delimited(
+        tag("("),
+        separated_list(tag(";"), filter),
+        tag(")"),
+    ))(i)?;
+    Ok((i, Filter::Call(name, args.unwrap_or_default())))
+}
+
+fn try_expr(i: &str) -> IResult<&str, Filter> {
+    let (i, body) = preceded(keyword("try"), postfix_term)(i)?;
+    let (i, catch) = opt(preceded(keyword("catch"), postfix_term))(i)?;
+    Ok((i, Filter::Try(Box::new(body), catch.map(Box::new))))
+}
+
+fn if_expr(i: &str) -> IResult<&str, Filter> {
+    let (i, cond) = preceded(keyword("if"), filter)(i)?;
+    let (i, then) = preceded(keyword("then"), filter)(i)?;
+    let (i, elifs) = many0(pair(
+        preceded(keyword("elif"), filter),
+        preceded(keyword("then"), filter),
+    ))(i)?;
+    let (i, otherwise) = opt(preceded(keyword("else"), filter))(i)?;
+    let (i, _) = keyword("end")(i)?;
+    let otherwise = elifs.into_iter().rev().fold(otherwise, |acc, (c, t)| {
+        Some(Filter::If(Box::new(c), Box::new(t), acc.map(Box::new)))
+    });
+    Ok((
+        i,
+        Filter::If(Box::new(cond), Box::new(then), otherwise.map(Box::new)),
+    ))
+}
+
+fn object_pattern_entry(i: &str) -> IResult<&str, (Filter, Option<String>, Option<Pattern>)> {
+    let colon = |i| delimited(multispace, tag(":"), multispace)(i);
+    if let Ok((i, name)) = var(i) {
+        let (i, p) = opt(preceded(colon, pattern))(i)?;
+        return Ok((i, (Filter::Literal(Value::String(name.clone())), Some(name), p)));
+    }
+    let (i, key) = alt((
+        map(alt((ident, string_lit)), |k| Filter::Literal(Value::String(k))),
+        filter_parens,
+    ))(i)?;
+    let (i, p) = preceded(colon, pattern)(i)?;
+    Ok((i, (key, None, Some(p))))
+}
+
+/// A destructuring pattern like `$x`, `[$a, $b]` or `{a: $x, $b}`.
+fn pattern(i: &str) -> IResult<&str, Pattern> {
+    alt((
+        map(var, Pattern::Var),
+        map(
+            delimited(
+                tag("["),
+                separated_list(tag(","), delimited(multispace, pattern, multispace)),
+                tag("]"),
+            ),
+            Pattern::Array,
+        ),
+        map(
+            delimited(
+                tag("{"),
+                separated_list(tag(","), delimited(multispace, object_pattern_entry, multispace)),
+                tag("}"),
+            ),
+            Pattern::Object,
+        ),
+    ))(i)
+}
+
+/// `p1 ?// p2 ...`, alternative patterns tried in order.
+fn patterns(i: &str) -> IResult<&str, Vec<Pattern>> {
+    separated_nonempty_list(tag("?//"), delimited(multispace, pattern, multispace))(i)
+}
+
+/// `source as patterns (`, the head shared by `reduce` and `foreach`.
+fn fold_head(i: &str) -> IResult<&str, (Filter, Vec<Pattern>)> {
+    let (i, source) = filter_term(i)?;
+    let (i, patterns) = preceded(keyword("as"), patterns)(i)?;
+    let (i, _) = tag("(")(i)?;
+    Ok((i, (source, patterns)))
+}
+
+fn reduce_expr(i: &str) -> IResult<&str, Filter> {
+    let (i, (source, patterns)) = preceded(keyword("reduce"), fold_head)(i)?;
+    let (i, init) = terminated(filter, tag(";"))(i)?;
+    let (i, update) = terminated(filter, tag(")"))(i)?;
+    Ok((
+        i,
+        Filter::Reduce(
+            Box::new(source),
+            patterns,
+            Box::new(init),
+            Box::new(update),
+        ),
+    ))
+}
+
+fn foreach_expr(i: &str) -> IResult<&str, Filter> {
+    let (i, (source, patterns)) = preceded(keyword("foreach"), fold_head)(i)?;
+    let (i, init) = terminated(filter, tag(";"))(i)?;
+    let (i, update) = filter(i)?;
+    let (i, extract) = terminated(opt(preceded(tag(";"), filter)), tag(")"))(i)?;
+    Ok((
+        i,
+        Filter::Foreach(
+            Box::new(source),
+            patterns,
+            Box::new(init),
+            Box::new(update),
+            extract.map(Box::new),
+        ),
+    ))
+}
+
+fn primary(i: &str) -> IResult<&str, Filter> {
+    alt((
+        // `..` is short for `recurse`
+        map(tag(".."), |_| Filter::Call("recurse".to_string(), Vec::new())),
+        map(path, Filter::Path),
+        literal,
+        string_term,
+        format,
+        map(var, Filter::Var),
+        array,
+        object,
+        filter_parens,
+        try_expr,
+        if_expr,
+        reduce_expr,
+        foreach_expr,
+        call,
+    ))(i)
+}
+
+enum Suffix {
+    Try,
+    Path(Vec<Path>),
+}
+
+fn suffix(i: &str) -> IResult<&str, Suffix> {
+    alt((
+        map(tag("?"), |_| Suffix::Try),
+        map(many1(path_seg), Suffix::Path),
+    ))(i)
+}
+
+/// A term followed by `?` and further path segments, e.g. `.a?.b[0]`.
+fn postfix_term(i: &str) -> IResult<&str, Filter> {
+    let (i, initial) = primary(i)?;
+    let (i, suffixes) = many0(suffix)(i)?;
+    Ok((
+        i,
+        suffixes.into_iter().fold(initial, |acc, s| match s {
+            Suffix::Try => Filter::Try(Box::new(acc), None),
+            Suffix::Path(mut p) => {
+                p.insert(0, Path::Root);
+                Filter::Pipe(Box::new(acc), Box::new(Filter::Path(p)))
+            }
+        }),
+    ))
+}
+
+fn filter_term(i: &str) -> IResult<&str, Filter> {
+    delimited(multispace, postfix_term, multispace)(i)
+}
+
+fn cmp_op(i: &str) -> IResult<&str, CmpOp> {
+    alt((
+        map(tag("=="), |_| CmpOp::Eq),
+        map(tag("!="), |_| CmpOp::Ne),
+        map(tag("<="), |_| CmpOp::Le),
+        map(tag(">="), |_| CmpOp::Ge),
+        map(tag("<"), |_| CmpOp::Lt),
+        map(tag(">"), |_| CmpOp::Gt),
+    ))(i)
+}
+
+/// The operator `op`, but not when it starts `//` or an update like `+=`.
+fn arith_op<'a>(op: &'static str) -> impl Fn(&'a str) -> IResult<&'a str, &'a str> {
+    terminated(tag(op), not(one_of("=/")))
+}
+
+/// A parameter of a `def`, `true` for a `$name` value parameter.
+fn param(i: &str) -> IResult<&str, (bool, String)> {
+    alt((map(var, |v| (true, v)), map(ident, |f| (false, f))))(i)
+}
+
+/// `def name(params): body;`, a `$x` parameter becomes the filter parameter
+/// `x` with the body wrapped in `x as $x | body`.
+fn funcdef(i: &str) -> IResult<&str, FuncDef> {
+    let (i, name) = preceded(
+        keyword("def"),
+        verify(ident, |s: &str| !KEYWORDS.contains(&s)),
+    )(i)?;
+    let (i, params) = opt(delimited(
+        tag("("),
+        separated_nonempty_list(tag(";"), delimited(multispace, param, multispace)),
+        tag(")"),
+    ))(i)?;
+    let (i, body) = delimited(
+        delimited(multispace, tag(":"), multispace),
+        filter,
+        tag(";"),
+    )(i)?;
+    let params = params.unwrap_or_default();
+    let body = params.iter().rev().fold(body, |body, (value, p)| {
+        if *value {
+            Filter::Bind(
+                Box::new(Filter::Call(p.clone(), Vec::new())),
+                vec![Pattern::Var(p.clone())],
+                Box::new(body),
+            )
+        } else {
+            body
+        }
+    });
+    Ok((
+        i,
+        FuncDef {
+            name,
+            params: params.into_iter().map(|(_, p)| p).collect(),
+            body,
+        },
+    ))
+}
+
+/// A sequence of definitions, like the prelude.
+pub fn funcdefs(i: &str) -> IResult<&str, Vec<FuncDef>> {
+    terminated(many0(delimited(multispace, funcdef, multispace)), multispace)(i)
+}
+
+/// `def ...; rest`, the definition is visible in everything to its right.
+fn def_expr(i: &str) -> IResult<&str, Filter> {
+    let (i, def) = funcdef(i)?;
+    let (i, rest) = filter(i)?;
+    Ok((i, Filter::Def(Box::new(def), Box::new(rest))))
+}
+
+/// A term, binding its outputs if followed by `as`, the body after the `|`
+/// extends as far to the right as possible.
+fn binding_term(i: &str) -> IResult<&str, Filter> {
+    let (i, source) = filter_term(i)?;
+    match preceded(keyword("as"), patterns)(i) {
+        Ok((i, patterns)) => {
+            let (i, body) = preceded(tag("|"), filter)(i)?;
+            Ok((i, Filter::Bind(Box::new(source), patterns, Box::new(body))))
+        }
+        Err(_) => Ok((i, source)),
+    }
+}
+
+fn arith_factor(i: &str) -> IResult<&str, Filter> {
+    alt((
+        def_expr,
+        binding_term,
+        map(
+            preceded(delimited(multispace, tag("-"), multispace), filter_term),
+            |f| {
+                Filter::Arith(
+                    Oper::Sub,
+                    Box::new(Filter::Literal(Value::I64(0))),
+                    Box::new(f),
+                )
+            },
+        ),
+    ))(i)
+}
+
+/// Comparisons don't associate, `a < b < c` is a syntax error.
+fn comparison(i: &str) -> IResult<&str, Filter> {
+    let (i, lhs) = math_expr(i)?;
+    let (i, rhs) = opt(pair(cmp_op, math_expr))(i)?;
+    Ok((
+        i,
+        match rhs {
+            Some((op, rhs)) => Filter::Cmp(op, Box::new(lhs), Box::new(rhs)),
+            None => lhs,
+        },
+    ))
+}
+
+fn and_expr(i: &str) -> IResult<&str, Filter> {
+    let (i, initial) = comparison(i)?;
+    let (i, remainder) = many0(preceded(keyword("and"), comparison))(i)?;
+    Ok((
+        i,
+        remainder.into_iter().fold(initial, |acc, f| {
+            Filter::And(Box::new(acc), Box::new(f))
+        }),
+    ))
+}
+
+fn or_expr(i: &str) -> IResult<&str, Filter> {
+    let (i, initial) = and_expr(i)?;
+    let (i, remainder) = many0(preceded(keyword("or"), and_expr))(i)?;
+    Ok((
+        i,
+        remainder.into_iter().fold(initial, |acc, f| {
+            Filter::Or(Box::new(acc), Box::new(f))
+        }),
+    ))
+}
+
+fn assign_op(i: &str) -> IResult<&str, AssignOp> {
+    alt((
+        map(tag("//="), |_| AssignOp::Alt),
+        map(tag("|="), |_| AssignOp::Update),
+        map(tag("+="), |_| AssignOp::Arith(Oper::Add)),
+        map(tag("-="), |_| AssignOp::Arith(Oper::Sub)),
+        map(tag("*="), |_| AssignOp::Arith(Oper::Mul)),
+        map(tag("/="), |_| AssignOp::Arith(Oper::Div)),
+        map(tag("%="), |_| AssignOp::Arith(Oper::Mod)),
+        map(terminated(tag("="), not(tag("="))), |_| AssignOp::Set),
+    ))(i)
+}
+
+/// Assignments don't associate and bind tighter than `//`, like in jq
+/// `.a = 1 // 2` is `(.a = 1) // 2`.
+fn assignment(i: &str) -> IResult<&str, Filter> {
+    let (i, lhs) = or_expr(i)?;
+    let (i, rhs) = opt(pair(assign_op, or_expr))(i)?;
+    Ok((
+        i,
+        match rhs {
+            Some((op, rhs)) => Filter::Assign(op, Box::new(lhs), Box::new(rhs)),
+            None => lhs,
+        },
+    ))
+}
+
+/// `a // b // c` is `a // (b // c)`.
+fn alternative(i: &str) -> IResult<&str, Filter> {
+    let (i, lhs) = assignment(i)?;
+    let (i, rhs) = opt(preceded(terminated(tag("//"), not(tag("="))), alternative))(i)?;
+    Ok((
+        i,
+        match rhs {
+            Some(rhs) => Filter::Alternative(Box::new(lhs), Box::new(rhs)),
+            None => lhs,
+        },
+    ))
+}
+
+fn comma(i: &str) -> IResult<&str, Filter> {
+    let (i, initial) = alternative(i)?;
+    let (i, remainder) = many0(preceded(tag(","), alternative))(i)?;
+    Ok((
+        i,
+        remainder.into_iter().fold(initial, |acc, f| {
+            Filter::Comma(Box::new(acc), Box::new(f))
+        }),
+    ))
+}
+
+fn fold_pipe(initial: Filter, remainder: Vec<Filter>) -> Filter {
+    let mut stages = remainder;
+    stages.insert(0, initial);
+    let last = stages.pop().unwrap();
+    stages
+        .into_iter()
+        .rev()
+        .fold(last, |acc, f| Filter::Pipe(Box::new(f), Box::new(acc)))
+}
+
+/// Parses a jq filter, stages are separated by `|`.
+pub fn filter(i: &str) -> IResult<&str, Filter> {
+    let (i, initial) = comma(i)?;
+    let (i, remainder) = many0(preceded(tag("|"), comma))(i)?;
+    Ok((i, fold_pipe(initial, remainder)))
+}
+
+/// Parses a whole jq program, anything after the filter is a syntax error
+/// described the way jq words it.
+pub fn program(i: &str) -> Result<Filter, String> {
+    let rest = match terminated(filter, multispace)(i) {
+        Ok(("", f)) => return Ok(f),
+        Ok((rest, _)) => rest,
+        Err(nom::Err::Error((rest, _))) | Err(nom::Err::Failure((rest, _))) => rest,
+        Err(nom::Err::Incomplete(_)) => "",
+    };
+    let unexpected = match rest.chars().next() {
+        Some(c) => format!("'{}'", c),
+        None => "$end".to_string(),
+    };
+    let line = i[..i.len() - rest.len()].matches('\n').count() + 1;
+    Err(format!(
+        "syntax error, unexpected {} (Unix shell quoting issues?) at <top-level>, line {}:",
+        unexpected, line
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(i: &str) -> Filter {
+        match filter(i) {
+            Ok(("", f)) => f,
+            r => panic!("{:?} doesn't parse: {:?}", i, r),
+        }
+    }
+
+    fn key(k: &str) -> Filter {
+        Filter::Path(vec![Path::Root, Path::Key(k.to_string())])
+    }
+
+    fn pipe(lhs: Filter, rhs: Filter) -> Filter {
+        Filter::Pipe(Box::new(lhs), Box::new(rhs))
+    }
+
+    #[test]
+    fn identity_and_paths() {
+        assert_eq!(parse("."), Filter::Path(vec![Path::Root]));
+        assert_eq!(
+            parse(".a.b"),
+            Filter::Path(vec![
+                Path::Root,
+                Path::Key("a".to_string()),
+                Path::Key("b".to_string())
+            ])
+        );
+    }
+
+    #[test]
+    fn pipes_associate_to_the_right() {
+        assert_eq!(
+            parse(".a | .b|.c"),
+            pipe(key("a"), pipe(key("b"), key("c")))
+        );
+        assert_eq!(
+            parse(" .a | . "),
+            pipe(key("a"), Filter::Path(vec![Path::Root]))
+        );
+    }
+
+    #[test]
+    fn comma_binds_tighter_than_pipe() {
+        assert_eq!(
+            parse(".a, .b | .c"),
+            pipe(
+                Filter::Comma(Box::new(key("a")), Box::new(key("b"))),
+                key("c")
+            )
+        );
+        assert_eq!(
+            parse(".[], .a[ ]"),
+            Filter::Comma(
+                Box::new(Filter::Path(vec![Path::Root, Path::Iter])),
+                Box::new(Filter::Path(vec![
+                    Path::Root,
+                    Path::Key("a".to_string()),
+                    Path::Iter
+                ]))
+            )
+        );
+    }
+
+    fn call(name: &str, args: Vec<Filter>) -> Filter {
+        Filter::Call(name.to_string(), args)
+    }
+
+    #[test]
+    fn try_catch_and_optional() {
+        assert_eq!(
+            parse("try .a catch .b"),
+            Filter::Try(Box::new(key("a")), Some(Box::new(key("b"))))
+        );
+        assert_eq!(parse("try .a"), Filter::Try(Box::new(key("a")), None));
+        // `?` applies to the term before it, further segments index its output
+        assert_eq!(
+            parse(".a?.b"),
+            pipe(Filter::Try(Box::new(key("a")), None), key("b"))
+        );
+        assert_eq!(
+            parse("error(.a)?"),
+            Filter::Try(Box::new(call("error", vec![key("a")])), None)
+        );
+        // `catch` isn't a function name
+        assert!(filter("catch").is_err());
+    }
+
+    #[test]
+    fn quoted_and_bracketed_keys() {
+        let keys = |ks: &[&str]| {
+            let mut p = vec![Path::Root];
+            p.extend(ks.iter().map(|k| Path::Key(k.to_string())));
+            Filter::Path(p)
+        };
+        assert_eq!(parse(".foo_bar"), keys(&["foo_bar"]));
+        assert_eq!(parse(".Foo1._x"), keys(&["Foo1", "_x"]));
+        assert_eq!(parse(".\"foo-bar\""), keys(&["foo-bar"]));
+        assert_eq!(parse(".[\"a b\"].c"), keys(&["a b", "c"]));
+        assert_eq!(parse(".a[ \"\\u00e9\" ]"), keys(&["a", "\u{e9}"]));
+    }
+
+    #[test]
+    fn indices_and_slices() {
+        let seg = |p: Path| Filter::Path(vec![Path::Root, p]);
+        assert_eq!(parse(".[-1]"), seg(Path::Idx(-1)));
+        assert_eq!(parse(".[ 2 ]"), seg(Path::Idx(2)));
+        assert_eq!(parse(".[2:5]"), seg(Path::Slice(Some(2), Some(5))));
+        assert_eq!(parse(".[:-3]"), seg(Path::Slice(None, Some(-3))));
+        assert_eq!(parse(".[1:]"), seg(Path::Slice(Some(1), None)));
+        assert_eq!(parse(".[:]"), seg(Path::Slice(None, None)));
+    }
+
+    fn lit(v: Value) -> Filter {
+        Filter::Literal(v)
+    }
+
+    #[test]
+    fn json_literals() {
+        assert_eq!(parse("12"), lit(Value::I64(12)));
+        assert_eq!(parse("1.5"), lit(Value::F64(1.5)));
+        assert_eq!(parse("2e3"), lit(Value::F64(2000.0)));
+        assert_eq!(parse("-1.5E-1"), lit(Value::F64(-0.15)));
+        assert_eq!(parse("99999999999999999999"), lit(Value::F64(1e20)));
+        assert_eq!(
+            parse(r#""a\"\n\ud83d\ude00""#),
+            lit(Value::String("a\"\n\u{1f600}".to_string()))
+        );
+        assert_eq!(parse("true"), lit(Value::Bool(true)));
+        assert_eq!(parse(" false "), lit(Value::Bool(false)));
+        assert_eq!(parse("null"), lit(Value::Null));
+        // keywords need a word boundary
+        assert_eq!(parse("nullable"), call("nullable", vec![]));
+    }
+
+    fn string(s: &str) -> Filter {
+        lit(Value::String(s.to_string()))
+    }
+
+    #[test]
+    fn object_construction() {
+        assert_eq!(
+            parse(r#"{a: .x, b, "c": 1, (.k): .v | .w, $v, $x: 2}"#),
+            Filter::Object(vec![
+                (string("a"), key("x")),
+                (string("b"), key("b")),
+                (string("c"), lit(Value::I64(1))),
+                (key("k"), pipe(key("v"), key("w"))),
+                (string("v"), Filter::Var("v".to_string())),
+                (Filter::Var("x".to_string()), lit(Value::I64(2))),
+            ])
+        );
+        assert_eq!(parse("{}"), Filter::Object(vec![]));
+        // a computed key needs a value
+        assert!(filter("{(.a)}").is_err());
+    }
+
+    #[test]
+    fn array_construction() {
+        assert_eq!(parse("[]"), Filter::Array(None));
+        assert_eq!(parse("[ ]"), Filter::Array(None));
+        assert_eq!(
+            parse("[.[] | .a, 1]"),
+            Filter::Array(Some(Box::new(pipe(
+                Filter::Path(vec![Path::Root, Path::Iter]),
+                Filter::Comma(Box::new(key("a")), Box::new(lit(Value::I64(1))))
+            ))))
+        );
+    }
+
+    fn boxed(f: Filter) -> Box<Filter> {
+        Box::new(f)
+    }
+
+    #[test]
+    fn boolean_operators() {
+        // `and` binds tighter than `or`, both tighter than `,`
+        assert_eq!(
+            parse(".a or .b and .c, .d"),
+            Filter::Comma(
+                boxed(Filter::Or(
+                    boxed(key("a")),
+                    boxed(Filter::And(boxed(key("b")), boxed(key("c"))))
+                )),
+                boxed(key("d"))
+            )
+        );
+        assert_eq!(parse(".a | not"), pipe(key("a"), call("not", vec![])));
+        assert_eq!(parse("order"), call("order", vec![]));
+    }
+
+    #[test]
+    fn conditionals() {
+        assert_eq!(
+            parse("if .a then .b elif .c then .d else .e end"),
+            Filter::If(
+                boxed(key("a")),
+                boxed(key("b")),
+                Some(boxed(Filter::If(
+                    boxed(key("c")),
+                    boxed(key("d")),
+                    Some(boxed(key("e")))
+                )))
+            )
+        );
+        assert_eq!(
+            parse("if . then 1 end"),
+            Filter::If(
+                boxed(Filter::Path(vec![Path::Root])),
+                boxed(lit(Value::I64(1))),
+                None
+            )
+        );
+        assert!(filter("if . then 1").is_err());
+    }
+
+    #[test]
+    fn calls_with_arguments() {
+        assert_eq!(
+            parse("select(.a >= 1) | empty"),
+            pipe(
+                call(
+                    "select",
+                    vec![Filter::Cmp(
+                        CmpOp::Ge,
+                        boxed(key("a")),
+                        boxed(lit(Value::I64(1)))
+                    )]
+                ),
+                call("empty", vec![])
+            )
+        );
+        assert_eq!(
+            parse("f(.a; .b, .c)"),
+            call(
+                "f",
+                vec![key("a"), Filter::Comma(boxed(key("b")), boxed(key("c")))]
+            )
+        );
+    }
+
+    fn arith(oper: Oper, lhs: Filter, rhs: Filter) -> Filter {
+        Filter::Arith(oper, boxed(lhs), boxed(rhs))
+    }
+
+    #[test]
+    fn arithmetic_precedence() {
+        let n = |n| lit(Value::I64(n));
+        assert_eq!(
+            parse("1 + 2 * 3 % 4 - -.a"),
+            arith(
+                Oper::Sub,
+                arith(
+                    Oper::Add,
+                    n(1),
+                    arith(Oper::Mod, arith(Oper::Mul, n(2), n(3)), n(4))
+                ),
+                arith(Oper::Sub, n(0), key("a"))
+            )
+        );
+        assert_eq!(
+            parse(".a / .b < 1"),
+            Filter::Cmp(
+                CmpOp::Lt,
+                boxed(arith(Oper::Div, key("a"), key("b"))),
+                boxed(n(1))
+            )
+        );
+    }
+
+    #[test]
+    fn math_expressions_share_the_operator_grammar() {
+        let (rest, e) = exprs("let x = 7 % 3 * 2; x + 1").unwrap();
+        assert_eq!(rest, "");
+        assert_eq!(
+            format!("{:?}", e),
+            r#"[let "x" = ((7 % 3) * 2), ("x" + 1)]"#
+        );
+    }
+
+    fn var(name: &str) -> Pattern {
+        Pattern::Var(name.to_string())
+    }
+
+    #[test]
+    fn bindings_and_patterns() {
+        // the body extends as far right as possible
+        assert_eq!(
+            parse(".a as $x | $x | .b"),
+            Filter::Bind(
+                boxed(key("a")),
+                vec![var("x")],
+                boxed(pipe(Filter::Var("x".to_string()), key("b")))
+            )
+        );
+        assert_eq!(
+            parse(r#". as [$a, {b: $c, $d, "e": [$f], (.k): $g}] ?// $h | 1"#),
+            Filter::Bind(
+                boxed(Filter::Path(vec![Path::Root])),
+                vec![
+                    Pattern::Array(vec![
+                        var("a"),
+                        Pattern::Object(vec![
+                            (string("b"), None, Some(var("c"))),
+                            (string("d"), Some("d".to_string()), None),
+                            (string("e"), None, Some(Pattern::Array(vec![var("f")]))),
+                            (key("k"), None, Some(var("g"))),
+                        ])
+                    ]),
+                    var("h")
+                ],
+                boxed(lit(Value::I64(1)))
+            )
+        );
+        // a binding needs a body
+        assert!(filter(". as $x").is_err());
+    }
+
+    #[test]
+    fn reduce_and_foreach() {
+        let dot = || Filter::Path(vec![Path::Root]);
+        let x = || Filter::Var("x".to_string());
+        assert_eq!(
+            parse("reduce .[] as $x (0; . + $x)"),
+            Filter::Reduce(
+                boxed(Filter::Path(vec![Path::Root, Path::Iter])),
+                vec![var("x")],
+                boxed(lit(Value::I64(0))),
+                boxed(arith(Oper::Add, dot(), x()))
+            )
+        );
+        assert_eq!(
+            parse("foreach .a as [$x] (null; $x; [., $x])"),
+            Filter::Foreach(
+                boxed(key("a")),
+                vec![Pattern::Array(vec![var("x")])],
+                boxed(lit(Value::Null)),
+                boxed(x()),
+                Some(boxed(Filter::Array(Some(boxed(Filter::Comma(
+                    boxed(dot()),
+                    boxed(x())
+                ))))))
+            )
+        );
+        assert_eq!(
+            parse("foreach .[] as $x (0; 1)"),
+            Filter::Foreach(
+                boxed(Filter::Path(vec![Path::Root, Path::Iter])),
+                vec![var("x")],
+                boxed(lit(Value::I64(0))),
+                boxed(lit(Value::I64(1))),
+                None
+            )
+        );
+    }
+
+    #[test]
+    fn recursive_descent() {
+        assert_eq!(parse(".."), call("recurse", vec![]));
+        assert_eq!(
+            parse("[..]"),
+            Filter::Array(Some(boxed(call("recurse", vec![]))))
+        );
+        assert_eq!(parse(".. | .a"), pipe(call("recurse", vec![]), key("a")));
+        assert_eq!(
+            parse("recurse(.a; . != null)"),
+            call(
+                "recurse",
+                vec![
+                    key("a"),
+                    Filter::Cmp(
+                        CmpOp::Ne,
+                        boxed(Filter::Path(vec![Path::Root])),
+                        boxed(lit(Value::Null))
+                    )
+                ]
+            )
+        );
+    }
+
+    fn alt(lhs: Filter, rhs: Filter) -> Filter {
+        Filter::Alternative(boxed(lhs), boxed(rhs))
+    }
+
+    #[test]
+    fn alternative_operator() {
+        assert_eq!(
+            parse(".a // .b // .c"),
+            alt(key("a"), alt(key("b"), key("c")))
+        );
+        assert_eq!(
+            parse(".a // .b, .c"),
+            Filter::Comma(boxed(alt(key("a"), key("b"))), boxed(key("c")))
+        );
+        assert_eq!(
+            parse(".a or .b // .c"),
+            alt(Filter::Or(boxed(key("a")), boxed(key("b"))), key("c"))
+        );
+    }
+
+    fn text(s: &str) -> StringPart {
+        StringPart::Lit(s.to_string())
+    }
+
+    #[test]
+    fn string_interpolation() {
+        assert_eq!(parse(r#""a\nb""#), lit(Value::String("a\nb".to_string())));
+        assert_eq!(parse(r#""""#), lit(Value::String(String::new())));
+        assert_eq!(
+            parse(r#""a\(.b)c""#),
+            Filter::Interpolate(
+                "text".to_string(),
+                vec![text("a"), StringPart::Interp(key("b")), text("c")]
+            )
+        );
+        assert_eq!(
+            parse(r#""\(1)\("x" | .)""#),
+            Filter::Interpolate(
+                "text".to_string(),
+                vec![
+                    StringPart::Interp(lit(Value::I64(1))),
+                    StringPart::Interp(pipe(
+                        lit(Value::String("x".to_string())),
+                        Filter::Path(vec![Path::Root])
+                    ))
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn formats() {
+        assert_eq!(parse("@csv"), Filter::Format("csv".to_string()));
+        assert_eq!(
+            parse(r#"@base64 "x\(.)""#),
+            Filter::Interpolate(
+                "base64".to_string(),
+                vec![
+                    text("x"),
+                    StringPart::Interp(Filter::Path(vec![Path::Root]))
+                ]
+            )
+        );
+        assert_eq!(parse(r#"@sh "x""#), lit(Value::String("x".to_string())));
+    }
+
+    fn assign(op: AssignOp, lhs: Filter, rhs: Filter) -> Filter {
+        Filter::Assign(op, boxed(lhs), boxed(rhs))
+    }
+
+    #[test]
+    fn assignment_operators() {
+        let one = || lit(Value::I64(1));
+        assert_eq!(parse(".a = 1"), assign(AssignOp::Set, key("a"), one()));
+        assert_eq!(
+            parse(".a |= . + 1"),
+            assign(
+                AssignOp::Update,
+                key("a"),
+                arith(Oper::Add, Filter::Path(vec![Path::Root]), one())
+            )
+        );
+        assert_eq!(
+            parse(".a += 1"),
+            assign(AssignOp::Arith(Oper::Add), key("a"), one())
+        );
+        assert_eq!(
+            parse(".a %= 1"),
+            assign(AssignOp::Arith(Oper::Mod), key("a"), one())
+        );
+        assert_eq!(parse(".a //= 1"), assign(AssignOp::Alt, key("a"), one()));
+        assert_eq!(
+            parse(".a == 1"),
+            Filter::Cmp(CmpOp::Eq, boxed(key("a")), boxed(one()))
+        );
+        assert_eq!(
+            parse(".a = 1 // 2"),
+            alt(assign(AssignOp::Set, key("a"), one()), lit(Value::I64(2)))
+        );
+        assert_eq!(
+            parse(".a = 1, .b"),
+            Filter::Comma(
+                boxed(assign(AssignOp::Set, key("a"), one())),
+                boxed(key("b"))
+            )
+        );
+    }
+
+    #[test]
+    fn trailing_input_is_a_syntax_error() {
+        assert_eq!(program(" .a | .b \n"), Ok(parse(".a | .b")));
+        assert_eq!(
+            program(".a |"),
+            Err(
+                "syntax error, unexpected '|' (Unix shell quoting issues?) at <top-level>, line 1:"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            program(".a\n| .b )"),
+            Err(
+                "syntax error, unexpected ')' (Unix shell quoting issues?) at <top-level>, line 2:"
+                    .to_string()
+            )
+        );
+    }
+}
@@ -1,8 +1,21 @@
 use crate::compiler::*;
+use crate::jq::Filter;
+use crate::parser::funcdefs;
 
+use chrono::{
+    DateTime, Datelike, Duration, Local, NaiveDate, NaiveDateTime, TimeZone, Timelike, Utc,
+};
+use corosensei::stack::{DefaultStack, Stack};
+use corosensei::{Coroutine, CoroutineResult, Yielder};
 use inkwell::types::BasicTypeEnum;
 use inkwell::values::FunctionValue;
+use regex::{Captures, Match, RegexBuilder};
 use simd_json::value::ValueTrait;
+use simd_json::OwnedValue as Value;
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::ptr;
 
 #[derive(Debug, Clone, Copy)]
 pub enum JQType {
@@ -41,7 +54,7 @@ impl Prototype {
     }
 }
 
-pub static STDLIB: [Prototype; 5] = [
+pub static STDLIB: [Prototype; 86] = [
     Prototype {
         name: "printd",
         args: &[("json", JQType::JSON)],
@@ -71,54 +84,3637 @@ pub static STDLIB: [Prototype; 5] = [
         args: &[("json", JQType::JSON), ("idx", JQType::Integer)],
         ret: JQType::JSON,
     },
+    Prototype {
+        name: "jq_index",
+        args: &[("json", JQType::JSON), ("key", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_slice",
+        args: &[
+            ("json", JQType::JSON),
+            ("from", JQType::Integer),
+            ("to", JQType::Integer),
+        ],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_length",
+        args: &[("json", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_utf8bytelength",
+        args: &[("json", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_ascii_downcase",
+        args: &[("json", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_ascii_upcase",
+        args: &[("json", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_trim",
+        args: &[("json", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_ltrim",
+        args: &[("json", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_rtrim",
+        args: &[("json", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_explode",
+        args: &[("json", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_implode",
+        args: &[("json", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_tostring",
+        args: &[("json", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_tojson",
+        args: &[("json", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_fromjson",
+        args: &[("json", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_tonumber",
+        args: &[("json", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_ltrimstr",
+        args: &[("json", JQType::JSON), ("prefix", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_rtrimstr",
+        args: &[("json", JQType::JSON), ("suffix", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_startswith",
+        args: &[("json", JQType::JSON), ("prefix", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_endswith",
+        args: &[("json", JQType::JSON), ("suffix", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_split",
+        args: &[("json", JQType::JSON), ("separator", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_join",
+        args: &[("json", JQType::JSON), ("separator", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_indices",
+        args: &[("json", JQType::JSON), ("needle", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_iter_check",
+        args: &[("json", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_iter_len",
+        args: &[("json", JQType::JSON)],
+        ret: JQType::Integer,
+    },
+    Prototype {
+        name: "jq_iter_get",
+        args: &[("json", JQType::JSON), ("idx", JQType::Integer)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_array_new",
+        args: &[],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_array_push",
+        args: &[("array", JQType::JSON), ("value", JQType::JSON)],
+        ret: JQType::Void,
+    },
+    Prototype {
+        name: "jq_object_new",
+        args: &[],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_object_insert",
+        args: &[
+            ("object", JQType::JSON),
+            ("key", JQType::JSON),
+            ("value", JQType::JSON),
+        ],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_add",
+        args: &[("lhs", JQType::JSON), ("rhs", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_sub",
+        args: &[("lhs", JQType::JSON), ("rhs", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_mul",
+        args: &[("lhs", JQType::JSON), ("rhs", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_div",
+        args: &[("lhs", JQType::JSON), ("rhs", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_mod",
+        args: &[("lhs", JQType::JSON), ("rhs", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_compare",
+        args: &[("lhs", JQType::JSON), ("rhs", JQType::JSON)],
+        ret: JQType::Integer,
+    },
+    Prototype {
+        name: "jq_truthy",
+        args: &[("json", JQType::JSON)],
+        ret: JQType::Integer,
+    },
+    Prototype {
+        name: "jq_bool",
+        args: &[("b", JQType::Integer)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_iter_key",
+        args: &[("json", JQType::JSON), ("idx", JQType::Integer)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_path_append",
+        args: &[("path", JQType::JSON), ("key", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_invalid_path",
+        args: &[("json", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_getpath",
+        args: &[("json", JQType::JSON), ("path", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_setpath",
+        args: &[("json", JQType::JSON), ("path", JQType::JSON), ("value", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_delpaths",
+        args: &[("json", JQType::JSON), ("paths", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_to_entries",
+        args: &[("json", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_from_entries",
+        args: &[("json", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_match",
+        args: &[
+            ("json", JQType::JSON),
+            ("regex", JQType::Integer),
+            ("re", JQType::JSON),
+            ("flags", JQType::JSON),
+            ("mode", JQType::Integer),
+        ],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_capture_object",
+        args: &[("match", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_sub_step",
+        args: &[
+            ("state", JQType::JSON),
+            ("json", JQType::JSON),
+            ("match", JQType::JSON),
+            ("inserts", JQType::JSON),
+        ],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_sub_results",
+        args: &[("state", JQType::JSON), ("json", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_now",
+        args: &[("json", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_mktime",
+        args: &[("json", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_gmtime",
+        args: &[("json", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_localtime",
+        args: &[("json", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_strftime",
+        args: &[("json", JQType::JSON), ("format", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_strflocaltime",
+        args: &[("json", JQType::JSON), ("format", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_strptime",
+        args: &[("json", JQType::JSON), ("format", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_number_check",
+        args: &[("json", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_to_f64",
+        args: &[("json", JQType::JSON)],
+        ret: JQType::Float,
+    },
+    Prototype {
+        name: "jq_from_f64",
+        args: &[("x", JQType::Float)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_frexp",
+        args: &[("x", JQType::Float)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_significand",
+        args: &[("x", JQType::Float)],
+        ret: JQType::Float,
+    },
+    Prototype {
+        name: "jq_drem",
+        args: &[("x", JQType::Float), ("y", JQType::Float)],
+        ret: JQType::Float,
+    },
+    Prototype {
+        name: "jq_modf",
+        args: &[("x", JQType::Float)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_isinfinite",
+        args: &[("x", JQType::Float)],
+        ret: JQType::Integer,
+    },
+    Prototype {
+        name: "jq_isnan",
+        args: &[("x", JQType::Float)],
+        ret: JQType::Integer,
+    },
+    Prototype {
+        name: "jq_isnormal",
+        args: &[("x", JQType::Float)],
+        ret: JQType::Integer,
+    },
+    Prototype {
+        name: "scalbln",
+        args: &[("x", JQType::Float), ("e", JQType::Integer)],
+        ret: JQType::Float,
+    },
+    Prototype {
+        name: "jq_format",
+        args: &[("json", JQType::JSON), ("format", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_error",
+        args: &[("json", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_catch",
+        args: &[("error", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_gen_new",
+        args: &[("entry", JQType::Integer), ("input", JQType::JSON)],
+        ret: JQType::Integer,
+    },
+    Prototype {
+        name: "jq_gen_push",
+        args: &[("gen", JQType::Integer), ("value", JQType::JSON)],
+        ret: JQType::Void,
+    },
+    Prototype {
+        name: "jq_gen_arg",
+        args: &[("gen", JQType::Integer), ("idx", JQType::Integer)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_gen_closure",
+        args: &[
+            ("closure", JQType::JSON),
+            ("input", JQType::JSON),
+            ("paths", JQType::Integer),
+        ],
+        ret: JQType::Integer,
+    },
+    Prototype {
+        name: "jq_gen_next",
+        args: &[("gen", JQType::Integer)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_gen_done",
+        args: &[("gen", JQType::Integer)],
+        ret: JQType::Integer,
+    },
+    Prototype {
+        name: "jq_gen_mark",
+        args: &[],
+        ret: JQType::Integer,
+    },
+    Prototype {
+        name: "jq_gen_drop",
+        args: &[("mark", JQType::Integer)],
+        ret: JQType::Void,
+    },
+    Prototype {
+        name: "jq_yield",
+        args: &[("value", JQType::JSON)],
+        ret: JQType::Void,
+    },
+    Prototype {
+        name: "jq_enter",
+        args: &[("name", JQType::JSON)],
+        ret: JQType::JSON,
+    },
+    Prototype {
+        name: "jq_limit",
+        args: &[("n", JQType::JSON)],
+        ret: JQType::Integer,
+    },
+];
+
+const fn math(name: &'static str, args: &'static [(&'static str, JQType)]) -> Prototype {
+    Prototype {
+        name,
+        args,
+        ret: JQType::Float,
+    }
+}
+
+const X: (&str, JQType) = ("x", JQType::Float);
+const Y: (&str, JQType) = ("y", JQType::Float);
+const Z: (&str, JQType) = ("z", JQType::Float);
+
+/// The libm functions of one number jq has, the builtin is called like the
+/// function. Those not every libm has are in the `PRELUDE` or written here.
+pub static MATH_1: [Prototype; 35] = [
+    math("acos", &[X]),
+    math("acosh", &[X]),
+    math("asin", &[X]),
+    math("asinh", &[X]),
+    math("atan", &[X]),
+    math("atanh", &[X]),
+    math("cbrt", &[X]),
+    math("ceil", &[X]),
+    math("cos", &[X]),
+    math("cosh", &[X]),
+    math("exp", &[X]),
+    math("exp2", &[X]),
+    math("expm1", &[X]),
+    math("fabs", &[X]),
+    math("floor", &[X]),
+    math("j0", &[X]),
+    math("j1", &[X]),
+    math("lgamma", &[X]),
+    math("log", &[X]),
+    math("log10", &[X]),
+    math("log1p", &[X]),
+    math("log2", &[X]),
+    math("logb", &[X]),
+    math("nearbyint", &[X]),
+    math("rint", &[X]),
+    math("round", &[X]),
+    math("sin", &[X]),
+    math("sinh", &[X]),
+    math("sqrt", &[X]),
+    math("tan", &[X]),
+    math("tanh", &[X]),
+    math("tgamma", &[X]),
+    math("trunc", &[X]),
+    math("y0", &[X]),
+    math("y1", &[X]),
+];
+
+/// The libm functions of two numbers, `pow(x; y)` calls `pow`.
+pub static MATH_2: [Prototype; 9] = [
+    math("atan2", &[X, Y]),
+    math("copysign", &[X, Y]),
+    math("fdim", &[X, Y]),
+    math("fmax", &[X, Y]),
+    math("fmin", &[X, Y]),
+    math("fmod", &[X, Y]),
+    math("hypot", &[X, Y]),
+    math("nextafter", &[X, Y]),
+    math("pow", &[X, Y]),
+];
+
+/// The libm functions of three numbers.
+pub static MATH_3: [Prototype; 1] = [math("fma", &[X, Y, Z])];
+
+thread_local! {
+    /// Values created by the runtime while processing one input.
+    static ARENA: RefCell<Vec<Box<Value>>> = RefCell::new(Vec::new());
+    /// The entries of the objects `jq_iter_check` prepared for `.[]`, by
+    /// address, so every step of the iteration takes constant time.
+    static ENTRIES: RefCell<HashMap<usize, Vec<(*const String, *const Value)>>> =
+        RefCell::new(HashMap::new());
+    /// The generators of the `def` calls in progress, by index. Generators
+    /// are only created on top of the ones that are running, so dropping
+    /// the newest ones never drops a running one.
+    static GENERATORS: RefCell<Vec<Box<Generator>>> = RefCell::new(Vec::new());
+    /// The stacks of dropped generators, for the next ones.
+    static STACKS: RefCell<Vec<DefaultStack>> = RefCell::new(Vec::new());
+    static RUNNING: Cell<Running> = Cell::new(Running {
+        yielder: ptr::null(),
+        limit: 0,
+    });
+}
+
+/// The stack a generator runs on, a `def` recursing on its generator grows
+/// it. Only the pages that are used take up memory.
+const STACK_SIZE: usize = 64 << 20;
+
+/// How much of a generator's stack `jq_enter` leaves to the runtime
+/// functions compiled code calls.
+const STACK_RESERVE: usize = 1 << 20;
+
+/// The entry point of a function compiled for a `def`, it takes the input
+/// and the generator it runs on, whose arguments it loads with `jq_gen_arg`.
+pub type Entry = unsafe extern "C" fn(Wrap, i64) -> Wrap;
+
+/// A `def` call running on a stack of its own: it suspends at every output,
+/// which its caller consumes before it resumes it for the next one.
+struct Generator {
+    coroutine: Coroutine<(), Wrap, Wrap>,
+    /// The values the function takes after its input.
+    args: Vec<Wrap>,
+}
+
+/// The generator that is running, `yielder` is null outside of them.
+#[derive(Clone, Copy)]
+struct Running {
+    yielder: *const Yielder<(), Wrap>,
+    /// The lowest address its stack may grow to, see `jq_enter`.
+    limit: usize,
+}
+
+/// Builtins written in jq, every script is compiled inside these `def`s.
+pub const PRELUDE: &str = r#"
+def recurse(f): def r: ., (f | r); r;
+def recurse(f; cond): def r: ., (f | select(cond) | r); r;
+def recurse: recurse(.[]?);
+def repeat(f): def _repeat: ., (f | _repeat); _repeat;
+def first: .[0];
+def scalars: select(. < []);
+def del(f): delpaths([path(f)]);
+def paths: path(..) | select(. != []);
+def paths(node_filter): . as $dot | paths | select(. as $p | $dot | getpath($p) | node_filter);
+def leaf_paths: paths(scalars);
+def with_entries(f): to_entries | [.[] | f] | from_entries;
+def todateiso8601: strftime("%Y-%m-%dT%H:%M:%SZ");
+def fromdateiso8601: strptime("%Y-%m-%dT%H:%M:%SZ") | mktime;
+def todate: todateiso8601;
+def fromdate: fromdateiso8601;
+def date: todate;
+def dateadd(u; n): . + n;
+def datesub(u; n): . - n;
+def exp10: pow(10; .);
+def pow10: exp10;
+def gamma: lgamma;
+def index(i): indices(i) | .[0];
+def rindex(i): indices(i) | .[-1];
+def ascii: [.] | implode;
+"#;
+
+/// Wraps `script` in the definitions of the `PRELUDE`.
+pub fn with_prelude(script: Filter) -> Filter {
+    let (_, defs) = funcdefs(PRELUDE).expect("the prelude is valid jq");
+    defs.into_iter()
+        .rev()
+        .fold(script, |acc, def| Filter::Def(Box::new(def), Box::new(acc)))
+}
+
+static NULL: Value = Value::Null;
+static TRUE: Value = Value::Bool(true);
+static FALSE: Value = Value::Bool(false);
+
+/// Moves `v` into the arena, the returned pointer stays valid until `reset`.
+pub fn alloc(v: Value) -> *const Value {
+    let b = Box::new(v);
+    let p: *const Value = &*b;
+    ARENA.with(|a| a.borrow_mut().push(b));
+    p
+}
+
+/// Frees every value the runtime produced for the last input, along with the
+/// generators an error left suspended.
+pub fn reset() {
+    jq_gen_drop(0);
+    ARENA.with(|a| a.borrow_mut().clear());
+    ENTRIES.with(|e| e.borrow_mut().clear());
+}
+
+/// A `Wrap` holding the newly created value `v`.
+pub fn ok(v: Value) -> Wrap {
+    Wrap {
+        error: 0,
+        json: alloc(v),
+    }
+}
+
+/// A `Wrap` carrying the error `msg`.
+pub fn error(msg: String) -> Wrap {
+    Wrap {
+        error: 1,
+        json: alloc(Value::String(msg)),
+    }
+}
+
+/// The name jq uses for the type of `v` in messages.
+pub fn type_name(v: &Value) -> &'static str {
+    match v {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::F64(_) | Value::I64(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn as_f64(v: &Value) -> Option<f64> {
+    match v {
+        Value::I64(n) => Some(*n as f64),
+        Value::F64(n) => Some(*n),
+        _ => None,
+    }
+}
+
+/// A jq number, integral results are kept as integers so they print as such.
+pub fn number(n: f64) -> Value {
+    let integral = n.fract() == 0.0 && n.abs() < 9_007_199_254_740_992.0;
+    // `-0` stays a float, as an integer it would lose its sign
+    if integral && !(n == 0.0 && n.is_sign_negative()) {
+        Value::I64(n as i64)
+    } else {
+        Value::F64(n)
+    }
+}
+
+/// jq's total order of JSON values: null < false < true < numbers <
+/// strings < arrays < objects. Objects compare their sorted keys first and
+/// then the values of those keys.
+pub fn cmp_values(a: &Value, b: &Value) -> Ordering {
+    fn rank(v: &Value) -> u8 {
+        match v {
+            Value::Null => 0,
+            Value::Bool(false) => 1,
+            Value::Bool(true) => 2,
+            Value::I64(_) | Value::F64(_) => 3,
+            Value::String(_) => 4,
+            Value::Array(_) => 5,
+            Value::Object(_) => 6,
+        }
+    }
+    match (a, b) {
+        (Value::I64(a), Value::I64(b)) => a.cmp(b),
+        (Value::String(a), Value::String(b)) => a.cmp(b),
+        (Value::Array(a), Value::Array(b)) => a
+            .iter()
+            .zip(b.iter())
+            .map(|(a, b)| cmp_values(a, b))
+            .find(|o| *o != Ordering::Equal)
+            .unwrap_or_else(|| a.len().cmp(&b.len())),
+        (Value::Object(a), Value::Object(b)) => {
+            let mut ka: Vec<&String> = a.iter().map(|(k, _)| k).collect();
+            let mut kb: Vec<&String> = b.iter().map(|(k, _)| k).collect();
+            ka.sort();
+            kb.sort();
+            ka.cmp(&kb).then_with(|| {
+                ka.iter()
+                    .map(|k| cmp_values(a.get(k.as_str()).unwrap(), b.get(k.as_str()).unwrap()))
+                    .find(|o| *o != Ordering::Equal)
+                    .unwrap_or(Ordering::Equal)
+            })
+        }
+        _ => match (as_f64(a), as_f64(b)) {
+            // like in jq nan sorts below every number, itself included, so
+            // it is never equal to anything
+            (Some(a), Some(_)) if a.is_nan() => Ordering::Less,
+            (Some(_), Some(b)) if b.is_nan() => Ordering::Greater,
+            (Some(a), Some(b)) => a.partial_cmp(&b).unwrap(),
+            _ => rank(a).cmp(&rank(b)),
+        },
+    }
+}
+
+/// `v` as JSON text the way jq prints it: infinities are the largest finite
+/// numbers and NaN is `null`.
+pub fn dump(v: &Value) -> String {
+    let mut out = String::new();
+    dump_to(v, &mut out);
+    out
+}
+
+fn dump_to(v: &Value, out: &mut String) {
+    match v {
+        Value::F64(x) if x.is_nan() => out.push_str("null"),
+        Value::F64(x) if x.is_infinite() => {
+            if *x < 0.0 {
+                out.push('-');
+            }
+            out.push_str("1.7976931348623157e+308");
+        }
+        Value::Array(a) => {
+            out.push('[');
+            for (i, x) in a.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                dump_to(x, out);
+            }
+            out.push(']');
+        }
+        Value::Object(o) => {
+            out.push('{');
+            for (i, (k, x)) in o.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&Value::String(k.clone()).to_string());
+                out.push(':');
+                dump_to(x, out);
+            }
+            out.push('}');
+        }
+        v => out.push_str(&v.to_string()),
+    }
+}
+
+/// `v` serialized and cut down the way jq shortens values in messages.
+pub fn dump_trunc(v: &Value) -> String {
+    let mut s = dump(v);
+    if s.len() > 14 {
+        let mut end = 11;
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        s.truncate(end);
+        s.push_str("...");
+    }
+    s
+}
+
+#[used]
+static E_PRINTJSON: [extern "C" fn(Wrap); 1] = [printjson];
+#[no_mangle]
+pub extern "C" fn printjson(w: Wrap) {
+    println!("{:?}", unsafe { &*w.json });
+}
+
+#[used]
+static E_DBG: [extern "C" fn(); 1] = [dbg];
+#[no_mangle]
+pub extern "C" fn dbg() {
+    dbg!();
+}
+
+#[used]
+static E_GET_KEY: unsafe extern "C" fn(Wrap, *const u8, usize) -> Wrap = jq_get_key;
+#[no_mangle]
+pub unsafe extern "C" fn jq_get_key(mut wrap: Wrap, key: *const u8, len: usize) -> Wrap {
+    use std::slice::from_raw_parts;
+    use std::str;
+    let key_slice: &[u8] = from_raw_parts(key, len);
+    let key_str = str::from_utf8(key_slice).unwrap();
+    match &*wrap.json {
+        Value::Object(o) => wrap.json = o.get(key_str).unwrap_or(&NULL),
+        Value::Null => (),
+        v => {
+            return error(format!(
+                "Cannot index {} with \"{}\"",
+                type_name(v),
+                key_str
+            ))
+        }
+    }
+    wrap
+}
+
+#[used]
+static E_GET_IDX: unsafe extern "C" fn(Wrap, i64) -> Wrap = jq_get_idx;
+#[no_mangle]
+pub unsafe extern "C" fn jq_get_idx(mut wrap: Wrap, idx: i64) -> Wrap {
+    match &*wrap.json {
+        Value::Array(a) => {
+            let idx = if idx < 0 { a.len() as i64 + idx } else { idx };
+            wrap.json = if idx < 0 {
+                &NULL
+            } else {
+                a.get(idx as usize).unwrap_or(&NULL)
+            }
+        }
+        Value::Null => (),
+        v => return error(format!("Cannot index {} with number", type_name(v))),
+    }
+    wrap
+}
+
+#[used]
+static E_INDEX: unsafe extern "C" fn(Wrap, Wrap) -> Wrap = jq_index;
+/// `.[key]` for a key only known at runtime, a string or a number.
+#[no_mangle]
+pub unsafe extern "C" fn jq_index(wrap: Wrap, key: Wrap) -> Wrap {
+    match &*key.json {
+        Value::String(k) => jq_get_key(wrap, k.as_ptr(), k.len()),
+        k if as_f64(k).is_some() => jq_get_idx(wrap, as_f64(k).unwrap().floor() as i64),
+        k => match &*wrap.json {
+            Value::Null => wrap,
+            v => error(format!("Cannot index {} with {}", type_name(v), type_name(k))),
+        },
+    }
+}
+
+/// Resolves slice bounds against `len` the way jq does, negative bounds
+/// count from the end and everything is clamped to `0..=len`.
+fn slice_bounds(len: usize, from: i64, to: i64) -> (usize, usize) {
+    let len = len as i64;
+    let clamp = |i: i64| {
+        if i < 0 {
+            (len + i).max(0)
+        } else {
+            i.min(len)
+        }
+    };
+    let (from, to) = (clamp(from), clamp(to));
+    (from as usize, to.max(from) as usize)
+}
+
+#[used]
+static E_SLICE: unsafe extern "C" fn(Wrap, i64, i64) -> Wrap = jq_slice;
+#[no_mangle]
+pub unsafe extern "C" fn jq_slice(wrap: Wrap, from: i64, to: i64) -> Wrap {
+    match &*wrap.json {
+        Value::Array(a) => {
+            let (from, to) = slice_bounds(a.len(), from, to);
+            ok(Value::Array(a[from..to].to_vec()))
+        }
+        Value::String(s) => {
+            let (from, to) = slice_bounds(s.chars().count(), from, to);
+            ok(Value::String(s.chars().skip(from).take(to - from).collect()))
+        }
+        Value::Null => wrap,
+        v => error(format!("Cannot index {} with object", type_name(v))),
+    }
+}
+
+#[used]
+static E_LENGTH: unsafe extern "C" fn(Wrap) -> Wrap = jq_length;
+/// Codepoints of strings, elements of arrays and objects, the absolute value
+/// of numbers.
+#[no_mangle]
+pub unsafe extern "C" fn jq_length(wrap: Wrap) -> Wrap {
+    match &*wrap.json {
+        Value::Null => ok(Value::I64(0)),
+        Value::String(s) => ok(Value::I64(s.chars().count() as i64)),
+        Value::Array(a) => ok(Value::I64(a.len() as i64)),
+        Value::Object(o) => ok(Value::I64(o.len() as i64)),
+        v => match as_f64(v) {
+            Some(n) => ok(number(n.abs())),
+            None => error(format!("{} ({}) has no length", type_name(v), dump_trunc(v))),
+        },
+    }
+}
+
+/// Applies `f` to a string input, fails with `msg` for anything else.
+unsafe fn on_string<F>(wrap: &Wrap, msg: &str, f: F) -> Wrap
+where
+    F: FnOnce(&str) -> Value,
+{
+    match &*wrap.json {
+        Value::String(s) => ok(f(s)),
+        v => error(format!("{} ({}) {}", type_name(v), dump_trunc(v), msg)),
+    }
+}
+
+#[used]
+static E_UTF8BYTELENGTH: unsafe extern "C" fn(Wrap) -> Wrap = jq_utf8bytelength;
+#[no_mangle]
+pub unsafe extern "C" fn jq_utf8bytelength(wrap: Wrap) -> Wrap {
+    on_string(&wrap, "only strings have UTF-8 byte length", |s| {
+        Value::I64(s.len() as i64)
+    })
+}
+
+#[used]
+static E_ASCII_DOWNCASE: unsafe extern "C" fn(Wrap) -> Wrap = jq_ascii_downcase;
+#[no_mangle]
+pub unsafe extern "C" fn jq_ascii_downcase(wrap: Wrap) -> Wrap {
+    on_string(&wrap, "cannot be ascii_downcased", |s| {
+        Value::String(s.to_ascii_lowercase())
+    })
+}
+
+#[used]
+static E_ASCII_UPCASE: unsafe extern "C" fn(Wrap) -> Wrap = jq_ascii_upcase;
+#[no_mangle]
+pub unsafe extern "C" fn jq_ascii_upcase(wrap: Wrap) -> Wrap {
+    on_string(&wrap, "cannot be ascii_upcased", |s| {
+        Value::String(s.to_ascii_uppercase())
+    })
+}
+
+/// The whitespace `trim` removes.
+fn is_space(c: char) -> bool {
+    " \t\n\x0b\x0c\r".contains(c)
+}
+
+#[used]
+static E_TRIM: unsafe extern "C" fn(Wrap) -> Wrap = jq_trim;
+#[no_mangle]
+pub unsafe extern "C" fn jq_trim(wrap: Wrap) -> Wrap {
+    on_string(&wrap, "cannot be trimmed", |s| {
+        Value::String(s.trim_matches(is_space).to_string())
+    })
+}
+
+#[used]
+static E_LTRIM: unsafe extern "C" fn(Wrap) -> Wrap = jq_ltrim;
+#[no_mangle]
+pub unsafe extern "C" fn jq_ltrim(wrap: Wrap) -> Wrap {
+    on_string(&wrap, "cannot be trimmed", |s| {
+        Value::String(s.trim_start_matches(is_space).to_string())
+    })
+}
+
+#[used]
+static E_RTRIM: unsafe extern "C" fn(Wrap) -> Wrap = jq_rtrim;
+#[no_mangle]
+pub unsafe extern "C" fn jq_rtrim(wrap: Wrap) -> Wrap {
+    on_string(&wrap, "cannot be trimmed", |s| {
+        Value::String(s.trim_end_matches(is_space).to_string())
+    })
+}
+
+#[used]
+static E_LTRIMSTR: unsafe extern "C" fn(Wrap, Wrap) -> Wrap = jq_ltrimstr;
+/// The input without the prefix `prefix`, anything else is passed through.
+#[no_mangle]
+pub unsafe extern "C" fn jq_ltrimstr(wrap: Wrap, prefix: Wrap) -> Wrap {
+    match (&*wrap.json, &*prefix.json) {
+        (Value::String(s), Value::String(p)) if s.starts_with(p.as_str()) => {
+            ok(Value::String(s[p.len()..].to_string()))
+        }
+        _ => wrap,
+    }
+}
+
+#[used]
+static E_RTRIMSTR: unsafe extern "C" fn(Wrap, Wrap) -> Wrap = jq_rtrimstr;
+/// The input without the suffix `suffix`, anything else is passed through.
+#[no_mangle]
+pub unsafe extern "C" fn jq_rtrimstr(wrap: Wrap, suffix: Wrap) -> Wrap {
+    match (&*wrap.json, &*suffix.json) {
+        (Value::String(s), Value::String(p)) if s.ends_with(p.as_str()) => {
+            ok(Value::String(s[..s.len() - p.len()].to_string()))
+        }
+        _ => wrap,
+    }
+}
+
+#[used]
+static E_STARTSWITH: unsafe extern "C" fn(Wrap, Wrap) -> Wrap = jq_startswith;
+#[no_mangle]
+pub unsafe extern "C" fn jq_startswith(wrap: Wrap, prefix: Wrap) -> Wrap {
+    match (&*wrap.json, &*prefix.json) {
+        (Value::String(s), Value::String(p)) => ok(Value::Bool(s.starts_with(p.as_str()))),
+        _ => error("startswith() requires string inputs".to_string()),
+    }
+}
+
+#[used]
+static E_ENDSWITH: unsafe extern "C" fn(Wrap, Wrap) -> Wrap = jq_endswith;
+#[no_mangle]
+pub unsafe extern "C" fn jq_endswith(wrap: Wrap, suffix: Wrap) -> Wrap {
+    match (&*wrap.json, &*suffix.json) {
+        (Value::String(s), Value::String(p)) => ok(Value::Bool(s.ends_with(p.as_str()))),
+        _ => error("endswith() requires string inputs".to_string()),
+    }
+}
+
+#[used]
+static E_SPLIT: unsafe extern "C" fn(Wrap, Wrap) -> Wrap = jq_split;
+/// `split/1`, splits at every occurrence of a literal separator.
+#[no_mangle]
+pub unsafe extern "C" fn jq_split(wrap: Wrap, sep: Wrap) -> Wrap {
+    let parts = match (&*wrap.json, &*sep.json) {
+        (Value::String(s), Value::String(_)) if s.is_empty() => Vec::new(),
+        (Value::String(s), Value::String(sep)) if sep.is_empty() => {
+            s.chars().map(|c| Value::String(c.to_string())).collect()
+        }
+        (Value::String(s), Value::String(sep)) => s
+            .split(sep.as_str())
+            .map(|p| Value::String(p.to_string()))
+            .collect(),
+        _ => return error("split input and separator must be strings".to_string()),
+    };
+    ok(Value::Array(parts))
+}
+
+#[used]
+static E_JOIN: unsafe extern "C" fn(Wrap, Wrap) -> Wrap = jq_join;
+/// Joins the elements with `sep`, nulls are empty and other non strings are
+/// written as JSON.
+#[no_mangle]
+pub unsafe extern "C" fn jq_join(wrap: Wrap, sep: Wrap) -> Wrap {
+    let elements: Vec<&Value> = match &*wrap.json {
+        Value::Array(a) => a.iter().collect(),
+        Value::Object(o) => o.values().collect(),
+        v => {
+            return error(format!(
+                "Cannot iterate over {} ({})",
+                type_name(v),
+                dump_trunc(v)
+            ))
+        }
+    };
+    let sep = match &*sep.json {
+        Value::String(sep) => sep.as_str(),
+        Value::Null => "",
+        v if elements.len() > 1 => {
+            return binop_error(&Value::String(String::new()), v, "cannot be added")
+        }
+        _ => "",
+    };
+    let mut s = String::new();
+    for (i, e) in elements.iter().enumerate() {
+        if i > 0 {
+            s.push_str(sep);
+        }
+        match e {
+            Value::Null => (),
+            e => s.push_str(&to_string(e)),
+        }
+    }
+    ok(Value::String(s))
+}
+
+#[used]
+static E_EXPLODE: unsafe extern "C" fn(Wrap) -> Wrap = jq_explode;
+#[no_mangle]
+pub unsafe extern "C" fn jq_explode(wrap: Wrap) -> Wrap {
+    on_string(&wrap, "cannot be exploded, explode input must be a string", |s| {
+        Value::Array(s.chars().map(|c| Value::I64(c as i64)).collect())
+    })
+}
+
+#[used]
+static E_IMPLODE: unsafe extern "C" fn(Wrap) -> Wrap = jq_implode;
+/// The string of an array of codepoints, negative codepoints, surrogates and
+/// codepoints past U+10FFFF are rejected.
+#[no_mangle]
+pub unsafe extern "C" fn jq_implode(wrap: Wrap) -> Wrap {
+    let codepoints = match &*wrap.json {
+        Value::Array(a) => a,
+        v => {
+            return error(format!(
+                "{} ({}) cannot be imploded, implode input must be an array",
+                type_name(v),
+                dump_trunc(v)
+            ))
+        }
+    };
+    let mut s = String::new();
+    for c in codepoints {
+        match as_f64(c) {
+            Some(c) if c >= 0.0 && c <= f64::from(std::char::MAX as u32) => {
+                match std::char::from_u32(c as u32) {
+                    Some(c) => s.push(c),
+                    None => return error("Invalid codepoint literal".to_string()),
+                }
+            }
+            Some(_) => return error("Invalid codepoint literal".to_string()),
+            None => return error("Unicode codepoint must be numeric".to_string()),
+        }
+    }
+    ok(Value::String(s))
+}
+
+#[used]
+static E_TOSTRING: unsafe extern "C" fn(Wrap) -> Wrap = jq_tostring;
+#[no_mangle]
+pub unsafe extern "C" fn jq_tostring(wrap: Wrap) -> Wrap {
+    match &*wrap.json {
+        Value::String(_) => wrap,
+        v => ok(Value::String(dump(v))),
+    }
+}
+
+#[used]
+static E_TOJSON: unsafe extern "C" fn(Wrap) -> Wrap = jq_tojson;
+#[no_mangle]
+pub unsafe extern "C" fn jq_tojson(wrap: Wrap) -> Wrap {
+    ok(Value::String(dump(&*wrap.json)))
+}
+
+#[used]
+static E_FROMJSON: unsafe extern "C" fn(Wrap) -> Wrap = jq_fromjson;
+#[no_mangle]
+pub unsafe extern "C" fn jq_fromjson(wrap: Wrap) -> Wrap {
+    let s = match &*wrap.json {
+        Value::String(s) => s,
+        v => {
+            return error(format!(
+                "{} ({}) cannot be parsed as JSON",
+                type_name(v),
+                dump_trunc(v)
+            ))
+        }
+    };
+    let mut bytes = s.clone().into_bytes();
+    match simd_json::to_owned_value(&mut bytes) {
+        Ok(v) => ok(v),
+        Err(e) => error(format!("{} (while parsing '{}')", e, s)),
+    }
+}
+
+#[used]
+static E_TONUMBER: unsafe extern "C" fn(Wrap) -> Wrap = jq_tonumber;
+#[no_mangle]
+pub unsafe extern "C" fn jq_tonumber(wrap: Wrap) -> Wrap {
+    match &*wrap.json {
+        Value::I64(_) | Value::F64(_) => wrap,
+        Value::String(s) => match s.trim().parse::<f64>() {
+            Ok(n) if n.is_finite() => ok(number(n)),
+            _ => error(format!("Cannot parse '{}' as a number", s)),
+        },
+        v => error(format!(
+            "{} ({}) cannot be parsed as a number",
+            type_name(v),
+            dump_trunc(v)
+        )),
+    }
+}
+
+#[used]
+static E_INDICES: unsafe extern "C" fn(Wrap, Wrap) -> Wrap = jq_indices;
+/// Where `needle` occurs in the input, a substring in a string, an element
+/// or a run of elements in an array. Occurrences may overlap.
+#[no_mangle]
+pub unsafe extern "C" fn jq_indices(wrap: Wrap, needle: Wrap) -> Wrap {
+    let positions: Vec<usize> = match (&*wrap.json, &*needle.json) {
+        (Value::Null, _) => return wrap,
+        (_, Value::String(n)) if n.is_empty() => return ok(Value::Null),
+        (_, Value::Array(n)) if n.is_empty() => return ok(Value::Null),
+        (Value::String(s), Value::String(n)) => {
+            let chars: Vec<char> = s.chars().collect();
+            let n: Vec<char> = n.chars().collect();
+            (0..chars.len())
+                .filter(|&i| chars[i..].starts_with(&n))
+                .collect()
+        }
+        (Value::Array(a), Value::Array(n)) => (0..a.len())
+            .filter(|&i| {
+                a.len() - i >= n.len()
+                    && a[i..]
+                        .iter()
+                        .zip(n)
+                        .all(|(x, y)| cmp_values(x, y) == Ordering::Equal)
+            })
+            .collect(),
+        (Value::Array(a), n) => (0..a.len())
+            .filter(|&i| cmp_values(&a[i], n) == Ordering::Equal)
+            .collect(),
+        (v, n) => return error(index_error(v, n)),
+    };
+    ok(Value::Array(
+        positions.into_iter().map(|i| Value::I64(i as i64)).collect(),
+    ))
+}
+
+#[used]
+static E_ITER_CHECK: unsafe extern "C" fn(Wrap) -> Wrap = jq_iter_check;
+#[no_mangle]
+pub unsafe extern "C" fn jq_iter_check(wrap: Wrap) -> Wrap {
+    match &*wrap.json {
+        Value::Array(_) => wrap,
+        Value::Object(o) => {
+            let entries = o.iter().map(|(k, v)| (k as *const String, v as *const Value));
+            ENTRIES.with(|e| e.borrow_mut().insert(wrap.json as usize, entries.collect()));
+            wrap
+        }
+        v => error(format!(
+            "Cannot iterate over {} ({})",
+            type_name(v),
+            dump_trunc(v)
+        )),
+    }
+}
+
+#[used]
+static E_ITER_LEN: unsafe extern "C" fn(Wrap) -> i64 = jq_iter_len;
+#[no_mangle]
+pub unsafe extern "C" fn jq_iter_len(wrap: Wrap) -> i64 {
+    match &*wrap.json {
+        Value::Array(a) => a.len() as i64,
+        Value::Object(o) => o.len() as i64,
+        _ => 0,
+    }
+}
+
+/// Entry `idx` of the object at `o`, which `jq_iter_check` prepared.
+fn entry(o: *const Value, idx: i64) -> (*const String, *const Value) {
+    ENTRIES.with(|e| e.borrow()[&(o as usize)][idx as usize])
+}
+
+#[used]
+static E_ITER_GET: unsafe extern "C" fn(Wrap, i64) -> Wrap = jq_iter_get;
+#[no_mangle]
+pub unsafe extern "C" fn jq_iter_get(mut wrap: Wrap, idx: i64) -> Wrap {
+    match &*wrap.json {
+        Value::Array(a) => wrap.json = &a[idx as usize],
+        Value::Object(_) => wrap.json = entry(wrap.json, idx).1,
+        v => return error(format!("Cannot iterate over {}", type_name(v))),
+    }
+    wrap
+}
+
+#[used]
+static E_ITER_KEY: unsafe extern "C" fn(Wrap, i64) -> Wrap = jq_iter_key;
+/// The path component of the element `jq_iter_get` returns for `idx`.
+#[no_mangle]
+pub unsafe extern "C" fn jq_iter_key(wrap: Wrap, idx: i64) -> Wrap {
+    match &*wrap.json {
+        Value::Object(o) => ok(Value::String(o.iter().nth(idx as usize).unwrap().0.clone())),
+        _ => ok(Value::I64(idx)),
+    }
+}
+
+#[used]
+static E_ERROR: extern "C" fn(Wrap) -> Wrap = jq_error;
+#[no_mangle]
+pub extern "C" fn jq_error(mut wrap: Wrap) -> Wrap {
+    wrap.error = 1;
+    wrap
+}
+
+#[used]
+static E_CATCH: extern "C" fn(Wrap) -> Wrap = jq_catch;
+#[no_mangle]
+pub extern "C" fn jq_catch(mut wrap: Wrap) -> Wrap {
+    wrap.error = 0;
+    wrap
+}
+
+#[used]
+static E_ARRAY_NEW: extern "C" fn() -> Wrap = jq_array_new;
+#[no_mangle]
+pub extern "C" fn jq_array_new() -> Wrap {
+    ok(Value::Array(Vec::new()))
+}
+
+#[used]
+static E_ARRAY_PUSH: unsafe extern "C" fn(Wrap, Wrap) = jq_array_push;
+/// Appends `value` to an array created by `jq_array_new` in place.
+#[no_mangle]
+pub unsafe extern "C" fn jq_array_push(array: Wrap, value: Wrap) {
+    if let Value::Array(a) = &mut *(array.json as *mut Value) {
+        a.push((*value.json).clone());
+    }
+}
+
+#[used]
+static E_OBJECT_NEW: extern "C" fn() -> Wrap = jq_object_new;
+#[no_mangle]
+pub extern "C" fn jq_object_new() -> Wrap {
+    ok(Value::Object(Default::default()))
+}
+
+#[used]
+static E_OBJECT_INSERT: unsafe extern "C" fn(Wrap, Wrap, Wrap) -> Wrap = jq_object_insert;
+/// Inserts into `object` in place, which like the arrays `jq_array_push`
+/// changes must come from `jq_object_new` and not have been handed to any
+/// other code yet.
+#[no_mangle]
+pub unsafe extern "C" fn jq_object_insert(object: Wrap, key: Wrap, value: Wrap) -> Wrap {
+    match (&mut *(object.json as *mut Value), &*key.json) {
+        (Value::Object(o), Value::String(k)) => {
+            o.insert(k.clone(), (*value.json).clone());
+            object
+        }
+        _ => error("Object keys must be strings".to_string()),
+    }
+}
+
+#[used]
+static E_COMPARE: unsafe extern "C" fn(Wrap, Wrap) -> i64 = jq_compare;
+#[no_mangle]
+pub unsafe extern "C" fn jq_compare(lhs: Wrap, rhs: Wrap) -> i64 {
+    match cmp_values(&*lhs.json, &*rhs.json) {
+        Ordering::Less => -1,
+        Ordering::Equal => 0,
+        Ordering::Greater => 1,
+    }
+}
+
+#[used]
+static E_TRUTHY: unsafe extern "C" fn(Wrap) -> i64 = jq_truthy;
+/// Only `false` and `null` are falsy.
+#[no_mangle]
+pub unsafe extern "C" fn jq_truthy(wrap: Wrap) -> i64 {
+    match &*wrap.json {
+        Value::Null | Value::Bool(false) => 0,
+        _ => 1,
+    }
+}
+
+#[used]
+static E_BOOL: extern "C" fn(i64) -> Wrap = jq_bool;
+#[no_mangle]
+pub extern "C" fn jq_bool(b: i64) -> Wrap {
+    Wrap {
+        error: 0,
+        json: if b != 0 { &TRUE } else { &FALSE },
+    }
+}
+
+fn binop_error(a: &Value, b: &Value, what: &str) -> Wrap {
+    error(format!(
+        "{} ({}) and {} ({}) {}",
+        type_name(a),
+        dump_trunc(a),
+        type_name(b),
+        dump_trunc(b),
+        what
+    ))
+}
+
+/// Merges `b` into `a`, keys present as objects in both are merged as well.
+fn deep_merge(a: &Value, b: &Value) -> Value {
+    match (a, b) {
+        (Value::Object(x), Value::Object(y)) => {
+            let mut r = x.clone();
+            for (k, v) in y.iter() {
+                let merged = match r.get(k) {
+                    Some(old) => deep_merge(old, v),
+                    None => v.clone(),
+                };
+                r.insert(k.clone(), merged);
+            }
+            Value::Object(r)
+        }
+        (_, b) => b.clone(),
+    }
+}
+
+/// Splits `s` on `sep` the way jq does, an empty separator splits into
+/// characters.
+pub fn split_str(s: &str, sep: &str) -> Vec<Value> {
+    if s.is_empty() {
+        Vec::new()
+    } else if sep.is_empty() {
+        s.chars().map(|c| Value::String(c.to_string())).collect()
+    } else {
+        s.split(sep).map(|p| Value::String(p.to_string())).collect()
+    }
+}
+
+#[used]
+static E_ADD: unsafe extern "C" fn(Wrap, Wrap) -> Wrap = jq_add;
+#[no_mangle]
+pub unsafe extern "C" fn jq_add(lhs: Wrap, rhs: Wrap) -> Wrap {
+    let (a, b) = (&*lhs.json, &*rhs.json);
+    match (a, b) {
+        (Value::Null, _) => rhs,
+        (_, Value::Null) => lhs,
+        (Value::I64(x), Value::I64(y)) if x.checked_add(*y).is_some() => ok(Value::I64(x + y)),
+        (Value::String(x), Value::String(y)) => ok(Value::String(format!("{}{}", x, y))),
+        (Value::Array(x), Value::Array(y)) => {
+            let mut r = x.clone();
+            r.extend(y.iter().cloned());
+            ok(Value::Array(r))
+        }
+        (Value::Object(x), Value::Object(y)) => {
+            let mut r = x.clone();
+            for (k, v) in y.iter() {
+                r.insert(k.clone(), v.clone());
+            }
+            ok(Value::Object(r))
+        }
+        _ => match (as_f64(a), as_f64(b)) {
+            (Some(x), Some(y)) => ok(number(x + y)),
+            _ => binop_error(a, b, "cannot be added"),
+        },
+    }
+}
+
+#[used]
+static E_SUB: unsafe extern "C" fn(Wrap, Wrap) -> Wrap = jq_sub;
+#[no_mangle]
+pub unsafe extern "C" fn jq_sub(lhs: Wrap, rhs: Wrap) -> Wrap {
+    let (a, b) = (&*lhs.json, &*rhs.json);
+    match (a, b) {
+        (Value::I64(x), Value::I64(y)) if x.checked_sub(*y).is_some() => ok(Value::I64(x - y)),
+        (Value::Array(x), Value::Array(y)) => ok(Value::Array(
+            x.iter()
+                .filter(|v| !y.iter().any(|w| cmp_values(v, w) == Ordering::Equal))
+                .cloned()
+                .collect(),
+        )),
+        _ => match (as_f64(a), as_f64(b)) {
+            (Some(x), Some(y)) => ok(number(x - y)),
+            _ => binop_error(a, b, "cannot be subtracted"),
+        },
+    }
+}
+
+#[used]
+static E_MUL: unsafe extern "C" fn(Wrap, Wrap) -> Wrap = jq_mul;
+#[no_mangle]
+pub unsafe extern "C" fn jq_mul(lhs: Wrap, rhs: Wrap) -> Wrap {
+    let (a, b) = (&*lhs.json, &*rhs.json);
+    match (a, b) {
+        (Value::I64(x), Value::I64(y)) if x.checked_mul(*y).is_some() => ok(Value::I64(x * y)),
+        (Value::Object(_), Value::Object(_)) => ok(deep_merge(a, b)),
+        (Value::String(s), n) | (n, Value::String(s)) if as_f64(n).is_some() => {
+            // like jq: "x" * n repeats the string, n <= 0 gives null
+            let n = (as_f64(n).unwrap() - 1.0) as i64;
+            if n < 0 {
+                return ok(Value::Null);
+            }
+            let n = n as usize + 1;
+            match n.checked_mul(s.len()) {
+                Some(len) if len <= std::i32::MAX as usize => ok(Value::String(s.repeat(n))),
+                _ => error("Repeat string result too long".to_string()),
+            }
+        }
+        _ => match (as_f64(a), as_f64(b)) {
+            (Some(x), Some(y)) => ok(number(x * y)),
+            _ => binop_error(a, b, "cannot be multiplied"),
+        },
+    }
+}
+
+#[used]
+static E_DIV: unsafe extern "C" fn(Wrap, Wrap) -> Wrap = jq_div;
+#[no_mangle]
+pub unsafe extern "C" fn jq_div(lhs: Wrap, rhs: Wrap) -> Wrap {
+    let (a, b) = (&*lhs.json, &*rhs.json);
+    match (a, b) {
+        (Value::String(x), Value::String(y)) => ok(Value::Array(split_str(x, y))),
+        _ => match (as_f64(a), as_f64(b)) {
+            (Some(_), Some(y)) if y == 0.0 => {
+                binop_error(a, b, "cannot be divided because the divisor is zero")
+            }
+            (Some(x), Some(y)) => ok(number(x / y)),
+            _ => binop_error(a, b, "cannot be divided"),
+        },
+    }
+}
+
+#[used]
+static E_MOD: unsafe extern "C" fn(Wrap, Wrap) -> Wrap = jq_mod;
+#[no_mangle]
+pub unsafe extern "C" fn jq_mod(lhs: Wrap, rhs: Wrap) -> Wrap {
+    let (a, b) = (&*lhs.json, &*rhs.json);
+    match (as_f64(a), as_f64(b)) {
+        (Some(_), Some(y)) if y as i64 == 0 => {
+            binop_error(a, b, "cannot be divided because the divisor is zero")
+        }
+        (Some(x), Some(y)) => ok(Value::I64((x as i64).wrapping_rem(y as i64))),
+        _ => binop_error(a, b, "cannot be divided"),
+    }
+}
+
+/// The bounds a `{"start": s, "end": e}` path component selects in a
+/// sequence of length `len`.
+fn slice_of(key: &Value, len: usize) -> Result<(usize, usize), String> {
+    let bound = |name: &str, open: i64| match key.as_object().and_then(|o| o.get(name)) {
+        None | Some(Value::Null) => Ok(open),
+        Some(b) => as_f64(b)
+            .map(|b| b as i64)
+            .ok_or_else(|| "Start and end indices of an array slice must be numbers".to_string()),
+    };
+    Ok(slice_bounds(len, bound("start", 0)?, bound("end", std::i64::MAX)?))
+}
+
+/// The index a number path component selects in an array of length `len`.
+fn index_of(key: &Value, len: usize) -> Option<usize> {
+    let idx = as_f64(key)?.floor() as i64;
+    let idx = if idx < 0 { len as i64 + idx } else { idx };
+    if idx < 0 {
+        None
+    } else {
+        Some(idx as usize)
+    }
+}
+
+fn index_error(v: &Value, key: &Value) -> String {
+    match key {
+        Value::String(k) => format!("Cannot index {} with \"{}\"", type_name(v), k),
+        k if as_f64(k).is_some() => format!("Cannot index {} with number", type_name(v)),
+        k => format!("Cannot index {} with {}", type_name(v), type_name(k)),
+    }
+}
+
+/// `v[key]` for a path component, slices are the only values created.
+fn get_component(v: &Value, key: &Value) -> Result<*const Value, String> {
+    match (v, key) {
+        (Value::Null, _) => Ok(&NULL),
+        (Value::Object(o), Value::String(k)) => Ok(o.get(k).unwrap_or(&NULL)),
+        (Value::Array(a), k) if as_f64(k).is_some() => {
+            Ok(index_of(k, a.len()).and_then(|i| a.get(i)).unwrap_or(&NULL))
+        }
+        (Value::Array(a), Value::Object(_)) => {
+            let (from, to) = slice_of(key, a.len())?;
+            Ok(alloc(Value::Array(a[from..to].to_vec())))
+        }
+        (Value::String(s), Value::Object(_)) => {
+            let (from, to) = slice_of(key, s.chars().count())?;
+            Ok(alloc(Value::String(s.chars().skip(from).take(to - from).collect())))
+        }
+        (v, key) => Err(index_error(v, key)),
+    }
+}
+
+fn set_path(v: &Value, path: &[Value], x: &Value) -> Result<Value, String> {
+    let (key, rest) = match path.split_first() {
+        Some(p) => p,
+        None => return Ok(x.clone()),
+    };
+    match (v, key) {
+        (Value::Object(_), Value::String(k)) | (Value::Null, Value::String(k)) => {
+            let mut o = match v {
+                Value::Object(o) => o.clone(),
+                _ => Default::default(),
+            };
+            let new = set_path(o.get(k).unwrap_or(&NULL), rest, x)?;
+            o.insert(k.clone(), new);
+            Ok(Value::Object(o))
+        }
+        (Value::Array(_), k) | (Value::Null, k) if as_f64(k).is_some() => {
+            let mut a = match v {
+                Value::Array(a) => a.clone(),
+                _ => Vec::new(),
+            };
+            let i = index_of(k, a.len()).ok_or_else(|| "Out of bounds negative array index".to_string())?;
+            if i >= a.len() {
+                a.resize(i + 1, Value::Null);
+            }
+            a[i] = set_path(&a[i], rest, x)?;
+            Ok(Value::Array(a))
+        }
+        (Value::Array(_), Value::Object(_)) | (Value::Null, Value::Object(_)) => {
+            let mut a = match v {
+                Value::Array(a) => a.clone(),
+                _ => Vec::new(),
+            };
+            let (from, to) = slice_of(key, a.len())?;
+            match set_path(&Value::Array(a[from..to].to_vec()), rest, x)? {
+                Value::Array(new) => {
+                    a.splice(from..to, new);
+                    Ok(Value::Array(a))
+                }
+                _ => Err("A slice of an array can only be assigned another array".to_string()),
+            }
+        }
+        (v, key) => Err(index_error(v, key)),
+    }
+}
+
+fn del_path(v: &Value, path: &[Value]) -> Result<Value, String> {
+    let (key, rest) = match path.split_first() {
+        Some(p) => p,
+        None => return Ok(Value::Null),
+    };
+    if !rest.is_empty() {
+        let child = unsafe { &*get_component(v, key)? };
+        if let Value::Null = child {
+            return Ok(v.clone());
+        }
+        let child = del_path(child, rest)?;
+        return set_path(v, &path[..1], &child);
+    }
+    match (v, key) {
+        (Value::Null, _) => Ok(Value::Null),
+        (Value::Object(o), Value::String(k)) => {
+            let mut o = o.clone();
+            o.remove(k);
+            Ok(Value::Object(o))
+        }
+        (Value::Array(a), k) if as_f64(k).is_some() => {
+            let mut a = a.clone();
+            match index_of(k, a.len()) {
+                Some(i) if i < a.len() => {
+                    a.remove(i);
+                }
+                _ => (),
+            }
+            Ok(Value::Array(a))
+        }
+        (Value::Array(a), Value::Object(_)) => {
+            let mut a = a.clone();
+            let (from, to) = slice_of(key, a.len())?;
+            a.drain(from..to);
+            Ok(Value::Array(a))
+        }
+        (v, Value::String(_)) => Err(format!(
+            "Cannot delete field at object index of {}",
+            type_name(v)
+        )),
+        (v, _) => Err(format!("Cannot delete field at index of {}", type_name(v))),
+    }
+}
+
+fn as_path(path: &Value) -> Result<&[Value], String> {
+    match path {
+        Value::Array(p) => Ok(p),
+        _ => Err("Path must be specified as an array".to_string()),
+    }
+}
+
+#[used]
+static E_PATH_APPEND: unsafe extern "C" fn(Wrap, Wrap) -> Wrap = jq_path_append;
+/// A copy of the array `path` with `key` appended, paths in path mode share
+/// their prefixes.
+#[no_mangle]
+pub unsafe extern "C" fn jq_path_append(path: Wrap, key: Wrap) -> Wrap {
+    let mut p = match &*path.json {
+        Value::Array(p) => p.clone(),
+        _ => Vec::new(),
+    };
+    p.push((*key.json).clone());
+    ok(Value::Array(p))
+}
+
+#[used]
+static E_INVALID_PATH: unsafe extern "C" fn(Wrap) -> Wrap = jq_invalid_path;
+#[no_mangle]
+pub unsafe extern "C" fn jq_invalid_path(wrap: Wrap) -> Wrap {
+    error(format!(
+        "Invalid path expression with result {}",
+        dump_trunc(&*wrap.json)
+    ))
+}
+
+#[used]
+static E_GETPATH: unsafe extern "C" fn(Wrap, Wrap) -> Wrap = jq_getpath;
+#[no_mangle]
+pub unsafe extern "C" fn jq_getpath(mut wrap: Wrap, path: Wrap) -> Wrap {
+    let path = match as_path(&*path.json) {
+        Ok(p) => p,
+        Err(e) => return error(e),
+    };
+    for key in path {
+        match get_component(&*wrap.json, key) {
+            Ok(v) => wrap.json = v,
+            Err(e) => return error(e),
+        }
+    }
+    wrap
+}
+
+#[used]
+static E_SETPATH: unsafe extern "C" fn(Wrap, Wrap, Wrap) -> Wrap = jq_setpath;
+#[no_mangle]
+pub unsafe extern "C" fn jq_setpath(wrap: Wrap, path: Wrap, value: Wrap) -> Wrap {
+    match as_path(&*path.json).and_then(|p| set_path(&*wrap.json, p, &*value.json)) {
+        Ok(v) => ok(v),
+        Err(e) => error(e),
+    }
+}
+
+#[used]
+static E_DELPATHS: unsafe extern "C" fn(Wrap, Wrap) -> Wrap = jq_delpaths;
+/// Deletes in descending order of the paths, so no deletion moves the values
+/// the remaining paths point to.
+#[no_mangle]
+pub unsafe extern "C" fn jq_delpaths(wrap: Wrap, paths: Wrap) -> Wrap {
+    let mut paths: Vec<&Value> = match &*paths.json {
+        Value::Array(p) => p.iter().collect(),
+        _ => return error("Paths must be specified as an array".to_string()),
+    };
+    paths.sort_by(|a, b| cmp_values(b, a));
+    let mut v = (*wrap.json).clone();
+    for p in paths {
+        match as_path(p).and_then(|p| del_path(&v, p)) {
+            Ok(new) => v = new,
+            Err(e) => return error(e),
+        }
+    }
+    ok(v)
+}
+
+#[used]
+static E_TO_ENTRIES: unsafe extern "C" fn(Wrap) -> Wrap = jq_to_entries;
+/// `{"key": k, "value": v}` for every key of an object or index of an array.
+#[no_mangle]
+pub unsafe extern "C" fn jq_to_entries(wrap: Wrap) -> Wrap {
+    let entry = |k: Value, v: &Value| {
+        let mut e: Value = Value::Object(Default::default());
+        if let Value::Object(o) = &mut e {
+            o.insert("key".to_string(), k);
+            o.insert("value".to_string(), v.clone());
+        }
+        e
+    };
+    match &*wrap.json {
+        Value::Object(o) => ok(Value::Array(
+            o.iter()
+                .map(|(k, v)| entry(Value::String(k.clone()), v))
+                .collect(),
+        )),
+        Value::Array(a) => ok(Value::Array(
+            a.iter()
+                .enumerate()
+                .map(|(i, v)| entry(Value::I64(i as i64), v))
+                .collect(),
+        )),
+        v => error(format!("{} ({}) has no keys", type_name(v), dump_trunc(v))),
+    }
+}
+
+/// The key and value of an entry for `from_entries`, the key is the first
+/// truthy of `key`, `k`, `name`, `Name`, `K` and `Key`, the value is `value`
+/// or `v`.
+fn from_entry(e: &Value) -> Result<(String, Value), String> {
+    let o = match e {
+        Value::Object(o) => o,
+        Value::Null => return Err("Cannot check whether null has a string key".to_string()),
+        v => return Err(index_error(v, &Value::String("key".to_string()))),
+    };
+    let key = ["key", "k", "name", "Name", "K", "Key"]
+        .iter()
+        .filter_map(|k| o.get(*k))
+        .find(|k| match k {
+            Value::Null | Value::Bool(false) => false,
+            _ => true,
+        })
+        .map(to_string)
+        .unwrap_or_else(|| "null".to_string());
+    let value = o.get("value").or_else(|| o.get("v")).unwrap_or(&NULL);
+    Ok((key, value.clone()))
+}
+
+#[used]
+static E_FROM_ENTRIES: unsafe extern "C" fn(Wrap) -> Wrap = jq_from_entries;
+#[no_mangle]
+pub unsafe extern "C" fn jq_from_entries(wrap: Wrap) -> Wrap {
+    let entries: Vec<&Value> = match &*wrap.json {
+        Value::Array(a) => a.iter().collect(),
+        Value::Object(o) => o.values().collect(),
+        v => {
+            return error(format!(
+                "Cannot iterate over {} ({})",
+                type_name(v),
+                dump_trunc(v)
+            ))
+        }
+    };
+    let mut object: Value = Value::Object(Default::default());
+    for e in entries {
+        match from_entry(e) {
+            Ok((k, v)) => {
+                if let Value::Object(o) = &mut object {
+                    o.insert(k, v);
+                }
+            }
+            Err(e) => return error(e),
+        }
+    }
+    ok(object)
+}
+
+static E_GEN_NEW: unsafe extern "C" fn(i64, Wrap) -> i64 = jq_gen_new;
+/// Creates a generator running the function whose `Entry` is stored at
+/// `entry` on `input` and returns its index. It starts once it is resumed,
+/// the arguments are pushed first.
+#[no_mangle]
+pub unsafe extern "C" fn jq_gen_new(entry: i64, input: Wrap) -> i64 {
+    let entry: Entry = std::mem::transmute(*(entry as *const usize));
+    let stack = match STACKS.with(|s| s.borrow_mut().pop()) {
+        Some(stack) => stack,
+        None => DefaultStack::new(STACK_SIZE).expect("cannot allocate a generator stack"),
+    };
+    let limit = stack.limit().get() + STACK_RESERVE;
+    GENERATORS.with(|g| {
+        let mut g = g.borrow_mut();
+        let gen = g.len() as i64;
+        let coroutine = Coroutine::with_stack(stack, move |yielder: &Yielder<(), Wrap>, ()| {
+            RUNNING.with(|r| r.set(Running { yielder, limit }));
+            entry(input, gen)
+        });
+        g.push(Box::new(Generator {
+            coroutine,
+            args: Vec::new(),
+        }));
+        gen
+    })
+}
+
+#[used]
+static E_GEN_PUSH: extern "C" fn(i64, Wrap) = jq_gen_push;
+#[no_mangle]
+pub extern "C" fn jq_gen_push(gen: i64, value: Wrap) {
+    GENERATORS.with(|g| g.borrow_mut()[gen as usize].args.push(value));
+}
+
+#[used]
+static E_GEN_ARG: extern "C" fn(i64, i64) -> Wrap = jq_gen_arg;
+#[no_mangle]
+pub extern "C" fn jq_gen_arg(gen: i64, idx: i64) -> Wrap {
+    GENERATORS.with(|g| {
+        let arg = &g.borrow()[gen as usize].args[idx as usize];
+        Wrap {
+            error: arg.error,
+            json: arg.json,
+        }
+    })
+}
+
+#[used]
+static E_GEN_CLOSURE: unsafe extern "C" fn(Wrap, Wrap, i64) -> i64 = jq_gen_closure;
+/// Creates a generator running a filter argument passed at runtime on
+/// `input`, in path mode if `paths` is set. The closure is an array of the
+/// `entry`s of the functions compiled for it without and with path mode and
+/// the arguments they take, the root of path mode is pushed by the caller.
+#[no_mangle]
+pub unsafe extern "C" fn jq_gen_closure(closure: Wrap, input: Wrap, paths: i64) -> i64 {
+    match &*closure.json {
+        Value::Array(a) => {
+            let gen = match a.get(paths as usize) {
+                Some(Value::I64(entry)) => jq_gen_new(*entry, input),
+                _ => unreachable!(),
+            };
+            for arg in &a[2..] {
+                jq_gen_push(gen, Wrap { error: 0, json: arg });
+            }
+            gen
+        }
+        _ => unreachable!(),
+    }
+}
+
+#[used]
+static E_GEN_NEXT: unsafe extern "C" fn(i64) -> Wrap = jq_gen_next;
+/// Resumes `gen` and returns its next output. Once its function returned
+/// `jq_gen_done` is true and this is the status it returned instead.
+#[no_mangle]
+pub unsafe extern "C" fn jq_gen_next(gen: i64) -> Wrap {
+    // the generator may create generators of its own while it runs, so the
+    // list must not stay borrowed
+    let g: *mut Generator = GENERATORS.with(|g| &mut *g.borrow_mut()[gen as usize] as *mut _);
+    let running = RUNNING.with(|r| r.get());
+    let result = (*g).coroutine.resume(());
+    RUNNING.with(|r| r.set(running));
+    match result {
+        CoroutineResult::Yield(w) | CoroutineResult::Return(w) => w,
+    }
+}
+
+#[used]
+static E_GEN_DONE: extern "C" fn(i64) -> i64 = jq_gen_done;
+#[no_mangle]
+pub extern "C" fn jq_gen_done(gen: i64) -> i64 {
+    GENERATORS.with(|g| g.borrow()[gen as usize].coroutine.done() as i64)
+}
+
+#[used]
+static E_GEN_MARK: extern "C" fn() -> i64 = jq_gen_mark;
+/// The index the next generator gets, for `jq_gen_drop`.
+#[no_mangle]
+pub extern "C" fn jq_gen_mark() -> i64 {
+    GENERATORS.with(|g| g.borrow().len() as i64)
+}
+
+#[used]
+static E_GEN_DROP: extern "C" fn(i64) = jq_gen_drop;
+/// Drops the generators from index `mark` on: a finished one along with
+/// the ones it left suspended when it failed, or the ones code jumping out
+/// of their loops leaves suspended.
+#[no_mangle]
+pub extern "C" fn jq_gen_drop(mark: i64) {
+    let dropped = GENERATORS.with(|g| {
+        let mut g = g.borrow_mut();
+        let mark = (mark as usize).min(g.len());
+        g.split_off(mark)
+    });
+    for gen in dropped.into_iter().rev() {
+        let Generator { mut coroutine, .. } = *gen;
+        if coroutine.started() {
+            // it is suspended in `jq_yield`, nothing on its stack needs to
+            // be dropped
+            unsafe { coroutine.force_reset() };
+        }
+        STACKS.with(|s| s.borrow_mut().push(coroutine.into_stack()));
+    }
+}
+
+#[used]
+static E_YIELD: unsafe extern "C" fn(Wrap) = jq_yield;
+/// Hands `value` to the consumer of the running generator, returns once it
+/// wants the next output.
+#[no_mangle]
+pub unsafe extern "C" fn jq_yield(value: Wrap) {
+    let running = RUNNING.with(|r| r.get());
+    (*running.yielder).suspend(value);
+    RUNNING.with(|r| r.set(running));
+}
+
+#[used]
+static E_ENTER: unsafe extern "C" fn(Wrap) -> Wrap = jq_enter;
+/// Called by the function of the `def` called `name` before it runs, fails
+/// once the stack of its generator is used up. A `def` recursing without
+/// end would overflow it otherwise.
+#[no_mangle]
+pub unsafe extern "C" fn jq_enter(name: Wrap) -> Wrap {
+    let here = &name as *const Wrap as usize;
+    if here < RUNNING.with(|r| r.get().limit) {
+        let name = match &*name.json {
+            Value::String(name) => name.as_str(),
+            _ => "def",
+        };
+        return error(format!("{} recursed too deeply", name));
+    }
+    Wrap {
+        error: 0,
+        json: &NULL,
+    }
+}
+
+#[used]
+static E_LIMIT: unsafe extern "C" fn(Wrap) -> i64 = jq_limit;
+/// How many outputs `limit(n; f)` takes, like jq all of them unless `n` is
+/// a number and none for `0`.
+#[no_mangle]
+pub unsafe extern "C" fn jq_limit(n: Wrap) -> i64 {
+    match &*n.json {
+        Value::I64(n) if *n >= 0 => *n,
+        Value::F64(n) if *n >= 0.0 && *n < std::i64::MAX as f64 => n.ceil() as i64,
+        _ => std::i64::MAX,
+    }
+}
+
+/// The formats `@name` can apply.
+pub const FORMATS: [&str; 11] = [
+    "text", "json", "html", "uri", "csv", "tsv", "sh", "base64", "base64d", "base32", "base32d",
 ];
 
+/// `tostring`, strings are used as they are and everything else as JSON.
+pub fn to_string(v: &Value) -> String {
+    match v {
+        Value::String(s) => s.clone(),
+        v => dump(v),
+    }
+}
+
+const BASE64: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const BASE32: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// Encodes `data` in groups of `bits` bits using `alphabet`, padding with
+/// `=` to a multiple of `block` characters.
+fn encode_base(data: &[u8], alphabet: &[u8], bits: u32, block: usize) -> String {
+    let mut out = String::new();
+    let (mut acc, mut n) = (0u32, 0u32);
+    for b in data {
+        acc = (acc << 8) | u32::from(*b);
+        n += 8;
+        while n >= bits {
+            n -= bits;
+            out.push(alphabet[((acc >> n) & ((1 << bits) - 1)) as usize] as char);
+        }
+        acc &= (1 << n) - 1;
+    }
+    if n > 0 {
+        out.push(alphabet[((acc << (bits - n)) & ((1 << bits) - 1)) as usize] as char);
+    }
+    while out.len() % block != 0 {
+        out.push('=');
+    }
+    out
+}
+
+/// The inverse of `encode_base`, `None` for characters not in `alphabet`.
+fn decode_base(text: &str, alphabet: &[u8], bits: u32) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    let (mut acc, mut n) = (0u32, 0u32);
+    for c in text.trim_end_matches('=').bytes() {
+        let d = alphabet.iter().position(|a| *a == c)? as u32;
+        acc = (acc << bits) | d;
+        n += bits;
+        if n >= 8 {
+            n -= 8;
+            out.push((acc >> n) as u8);
+            acc &= (1 << n) - 1;
+        }
+    }
+    Some(out)
+}
+
+/// A row for `@csv` or `@tsv`, `quote` formats the strings.
+fn format_row(v: &Value, name: &str, quote: fn(&str) -> String, sep: &str) -> Result<String, String> {
+    let row = match v {
+        Value::Array(a) => a,
+        v => {
+            return Err(format!(
+                "{} ({}) cannot be {}-formatted, only an array can be",
+                type_name(v),
+                dump_trunc(v),
+                name
+            ))
+        }
+    };
+    let mut cols = Vec::new();
+    for x in row {
+        cols.push(match x {
+            Value::Null => String::new(),
+            Value::String(s) => quote(s),
+            Value::Array(_) | Value::Object(_) => {
+                return Err(format!(
+                    "{} ({}) is not valid in a {} row",
+                    type_name(x),
+                    dump_trunc(x),
+                    name
+                ))
+            }
+            x => dump(x),
+        });
+    }
+    Ok(cols.join(sep))
+}
+
+fn format_value(v: &Value, format: &str) -> Result<String, String> {
+    Ok(match format {
+        "text" => to_string(v),
+        "json" => dump(v),
+        "html" => to_string(v)
+            .chars()
+            .map(|c| match c {
+                '<' => "&lt;".to_string(),
+                '>' => "&gt;".to_string(),
+                '&' => "&amp;".to_string(),
+                '\'' => "&#39;".to_string(),
+                '"' => "&quot;".to_string(),
+                c => c.to_string(),
+            })
+            .collect(),
+        "uri" => to_string(v)
+            .bytes()
+            .map(|b| match b {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                    (b as char).to_string()
+                }
+                b => format!("%{:02X}", b),
+            })
+            .collect(),
+        "csv" => format_row(v, "csv", |s| format!("\"{}\"", s.replace('"', "\"\"")), ",")?,
+        "tsv" => format_row(
+            v,
+            "tsv",
+            |s| {
+                s.replace('\\', "\\\\")
+                    .replace('\t', "\\t")
+                    .replace('\n', "\\n")
+                    .replace('\r', "\\r")
+            },
+            "\t",
+        )?,
+        "sh" => {
+            let quote = |x: &Value| match x {
+                Value::String(s) => Ok(format!("'{}'", s.replace('\'', "'\\''"))),
+                Value::Array(_) | Value::Object(_) => Err(format!(
+                    "{} ({}) can not be escaped for shell",
+                    type_name(x),
+                    dump_trunc(x)
+                )),
+                x => Ok(dump(x)),
+            };
+            match v {
+                Value::Array(a) => a.iter().map(quote).collect::<Result<Vec<_>, _>>()?.join(" "),
+                v => quote(v)?,
+            }
+        }
+        "base64" => encode_base(to_string(v).as_bytes(), BASE64, 6, 4),
+        "base32" => encode_base(to_string(v).as_bytes(), BASE32, 5, 8),
+        "base64d" | "base32d" => {
+            let text = to_string(v);
+            let decoded = if format == "base64d" {
+                decode_base(&text, BASE64, 6)
+            } else {
+                decode_base(&text, BASE32, 5)
+            };
+            match decoded {
+                Some(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+                None => {
+                    return Err(format!(
+                        "{} ({}) is not valid {} data",
+                        type_name(v),
+                        dump_trunc(v),
+                        &format[..6]
+                    ))
+                }
+            }
+        }
+        f => return Err(format!("{} is not a valid format", f)),
+    })
+}
+
+#[used]
+static E_FORMAT: unsafe extern "C" fn(Wrap, Wrap) -> Wrap = jq_format;
+/// `@format`, `format` is the name of one of the `FORMATS`.
+#[no_mangle]
+pub unsafe extern "C" fn jq_format(wrap: Wrap, format: Wrap) -> Wrap {
+    let format = match &*format.json {
+        Value::String(f) => f.as_str(),
+        _ => "text",
+    };
+    match format_value(&*wrap.json, format) {
+        Ok(s) => ok(Value::String(s)),
+        Err(e) => error(e),
+    }
+}
+
+/// A regex of the `test`, `match`, ... family with its flags applied.
+pub struct Regex {
+    regex: regex::Regex,
+    /// `g`, every match is used rather than the first.
+    global: bool,
+    /// `n`, empty matches are ignored.
+    skip_empty: bool,
+}
+
+/// Rewrites Oniguruma's `(?<name>...)` groups to the `(?P<name>...)` the
+/// regex crate understands, lookbehinds are left alone.
+fn named_groups(re: &str) -> String {
+    let mut out = String::with_capacity(re.len());
+    let mut chars = re.char_indices();
+    while let Some((i, c)) = chars.next() {
+        out.push(c);
+        match c {
+            '\\' => out.extend(chars.next().map(|(_, c)| c)),
+            '(' => {
+                let rest = &re[i + 1..];
+                if rest.starts_with("?<") && !rest.starts_with("?<=") && !rest.starts_with("?<!") {
+                    out.push_str("?P");
+                    chars.next();
+                }
+            }
+            _ => (),
+        }
+    }
+    out
+}
+
+/// Compiles `re` with jq's `flags`, `re` may also be a `[re, flags]` array
+/// when there are no flags.
+pub fn compile_regex(re: &Value, flags: &Value) -> Result<Regex, String> {
+    let (re, flags) = match (re, flags) {
+        (Value::Array(a), Value::Null) => (a.get(0).unwrap_or(&NULL), a.get(1).unwrap_or(&NULL)),
+        _ => (re, flags),
+    };
+    let re = match re {
+        Value::String(re) => re,
+        v => {
+            return Err(format!(
+                "{} ({}) cannot be matched, as it is not a string",
+                type_name(v),
+                dump_trunc(v)
+            ))
+        }
+    };
+    let flags = match flags {
+        Value::Null => "",
+        Value::String(f) => f,
+        v => return Err(format!("{} is not a string", dump_trunc(v))),
+    };
+    // `^` and `$` match at line breaks unless `s` is given, like Oniguruma's
+    // Ruby syntax jq uses
+    let re = named_groups(re);
+    let mut builder = RegexBuilder::new(&re);
+    builder.multi_line(true);
+    let (mut global, mut skip_empty) = (false, false);
+    for f in flags.chars() {
+        match f {
+            'g' => global = true,
+            'i' => {
+                builder.case_insensitive(true);
+            }
+            'x' => {
+                builder.ignore_whitespace(true);
+            }
+            'n' => skip_empty = true,
+            's' => {
+                builder.multi_line(false);
+            }
+            'p' => {
+                builder.multi_line(false).dot_matches_new_line(true);
+            }
+            // there is no leftmost longest matching, the first of the
+            // leftmost matches is used
+            'l' => (),
+            _ => return Err(format!("{} is not a valid modifier string", flags)),
+        }
+    }
+    match builder.build() {
+        Ok(regex) => Ok(Regex {
+            regex,
+            global,
+            skip_empty,
+        }),
+        Err(e) => Err(format!("{} (at offset 0) is not a valid regex: {}", re, e)),
+    }
+}
+
+impl Regex {
+    /// The matches in `s`, only the first one unless the regex is global or
+    /// `all` is set.
+    fn matches<'s>(&self, s: &'s str, all: bool) -> Vec<Captures<'s>> {
+        let skip_empty = self.skip_empty;
+        let matches = self
+            .regex
+            .captures_iter(s)
+            .filter(|c| !skip_empty || !c[0].is_empty());
+        if all || self.global {
+            matches.collect()
+        } else {
+            matches.take(1).collect()
+        }
+    }
+}
+
+fn object(entries: Vec<(&str, Value)>) -> Value {
+    let mut o: Value = Value::Object(Default::default());
+    if let Value::Object(map) = &mut o {
+        for (k, v) in entries {
+            map.insert(k.to_string(), v);
+        }
+    }
+    o
+}
+
+/// jq's match object, offsets and lengths count codepoints.
+fn match_object(s: &str, regex: &regex::Regex, caps: &Captures) -> Value {
+    let position = |m: Option<Match>| match m {
+        Some(m) => (
+            Value::I64(s[..m.start()].chars().count() as i64),
+            Value::I64(m.as_str().chars().count() as i64),
+            Value::String(m.as_str().to_string()),
+        ),
+        None => (Value::I64(-1), Value::I64(0), Value::Null),
+    };
+    let captures = regex
+        .capture_names()
+        .enumerate()
+        .skip(1)
+        .map(|(i, name)| {
+            let (offset, length, string) = position(caps.get(i));
+            object(vec![
+                ("offset", offset),
+                ("length", length),
+                ("string", string),
+                ("name", name.map_or(Value::Null, |n| Value::String(n.to_string()))),
+            ])
+        })
+        .collect();
+    let (offset, length, string) = position(caps.get(0));
+    object(vec![
+        ("offset", offset),
+        ("length", length),
+        ("string", string),
+        ("captures", Value::Array(captures)),
+    ])
+}
+
+/// The named captures of a match object as an object, what `capture` emits
+/// and `sub` runs the replacement on.
+fn capture_object(m: &Value) -> Value {
+    let mut o: Value = Value::Object(Default::default());
+    if let (Some(Value::Array(captures)), Value::Object(map)) =
+        (m.as_object().and_then(|m| m.get("captures")), &mut o)
+    {
+        for c in captures.iter().filter_map(|c| c.as_object()) {
+            if let Some(Value::String(name)) = c.get("name") {
+                map.insert(name.clone(), c.get("string").cloned().unwrap_or(Value::Null));
+            }
+        }
+    }
+    o
+}
+
+/// `jq_match` tests whether there is a match.
+pub const REGEX_TEST: i64 = 0;
+/// `jq_match` returns the match objects.
+pub const REGEX_MATCH: i64 = 1;
+/// `jq_match` returns the match objects of every match, as if `g` was given.
+pub const REGEX_GLOBAL: i64 = 2;
+/// `jq_match` returns the named captures of the matches.
+pub const REGEX_CAPTURE: i64 = 3;
+/// `jq_match` returns what `scan` emits for every match.
+pub const REGEX_SCAN: i64 = 4;
+/// `jq_match` returns the parts of the input between the matches.
+pub const REGEX_SPLIT: i64 = 5;
+
 #[used]
-static E_PRINTJSON: [extern "C" fn(Wrap); 1] = [printjson];
+static E_MATCH: unsafe extern "C" fn(Wrap, i64, Wrap, Wrap, i64) -> Wrap = jq_match;
+/// Matches the input against `regex`, a `Regex` compiled along with the
+/// script, or when it is `0` against `re` compiled with `flags`. What it
+/// returns depends on `mode`.
 #[no_mangle]
-pub extern "C" fn printjson(w: Wrap) {
-    println!("{:?}", unsafe { &*w.json });
+pub unsafe extern "C" fn jq_match(input: Wrap, regex: i64, re: Wrap, flags: Wrap, mode: i64) -> Wrap {
+    let s = match &*input.json {
+        Value::String(s) => s,
+        v => {
+            return error(format!(
+                "{} ({}) cannot be matched, as it is not a string",
+                type_name(v),
+                dump_trunc(v)
+            ))
+        }
+    };
+    let compiled;
+    let regex = if regex != 0 {
+        &*(regex as *const Regex)
+    } else {
+        match compile_regex(&*re.json, &*flags.json) {
+            Ok(r) => {
+                compiled = r;
+                &compiled
+            }
+            Err(e) => return error(e),
+        }
+    };
+    let v = match mode {
+        REGEX_TEST => Value::Bool(
+            regex
+                .regex
+                .find_iter(s)
+                .any(|m| !regex.skip_empty || !m.as_str().is_empty()),
+        ),
+        REGEX_MATCH | REGEX_GLOBAL => Value::Array(
+            regex
+                .matches(s, mode == REGEX_GLOBAL)
+                .iter()
+                .map(|c| match_object(s, &regex.regex, c))
+                .collect(),
+        ),
+        REGEX_CAPTURE => Value::Array(
+            regex
+                .matches(s, false)
+                .iter()
+                .map(|c| capture_object(&match_object(s, &regex.regex, c)))
+                .collect(),
+        ),
+        REGEX_SCAN => Value::Array(
+            regex
+                .matches(s, true)
+                .iter()
+                .map(|c| {
+                    if c.len() > 1 {
+                        Value::Array(
+                            c.iter()
+                                .skip(1)
+                                .map(|m| m.map_or(Value::Null, |m| Value::String(m.as_str().to_string())))
+                                .collect(),
+                        )
+                    } else {
+                        Value::String(c[0].to_string())
+                    }
+                })
+                .collect(),
+        ),
+        _ => {
+            let mut parts = Vec::new();
+            let mut previous = 0;
+            for c in regex.matches(s, true) {
+                let m = c.get(0).unwrap();
+                parts.push(Value::String(s[previous..m.start()].to_string()));
+                previous = m.end();
+            }
+            parts.push(Value::String(s[previous..].to_string()));
+            Value::Array(parts)
+        }
+    };
+    ok(v)
 }
 
 #[used]
-static E_DBG: [extern "C" fn(); 1] = [dbg];
+static E_CAPTURE_OBJECT: unsafe extern "C" fn(Wrap) -> Wrap = jq_capture_object;
 #[no_mangle]
-pub extern "C" fn dbg() {
-    dbg!();
+pub unsafe extern "C" fn jq_capture_object(m: Wrap) -> Wrap {
+    ok(capture_object(&*m.json))
+}
+
+/// The number at `key` of the object `v`, `0` if there is none.
+fn count_at(v: &Value, key: &str) -> usize {
+    v.as_object()
+        .and_then(|o| o.get(key))
+        .and_then(as_f64)
+        .map_or(0, |n| n.max(0.0) as usize)
 }
 
 #[used]
-static E_GET_KEY: unsafe extern "C" fn(Wrap, *const u8, usize) -> Wrap = jq_get_key;
+static E_SUB_STEP: unsafe extern "C" fn(Wrap, Wrap, Wrap, Wrap) -> Wrap = jq_sub_step;
+/// Appends the text before the match `m` and the `inserts` the replacement
+/// produced to the results in `state`, `{"result": [...], "previous": n}`
+/// where `n` is where the last match ended. Like in jq the nth insert goes
+/// into the nth result.
 #[no_mangle]
-pub unsafe extern "C" fn jq_get_key(mut wrap: Wrap, key: *const u8, len: usize) -> Wrap {
-    use std::slice::from_raw_parts;
-    use std::str;
-    let key_slice: &[u8] = from_raw_parts(key, len);
-    let key_str = str::from_utf8(key_slice).unwrap();
-    if let Some(o) = (&*wrap.json).as_object() {
-        if let Some(v) = o.get(key_str) {
-            wrap.json = v;
-        } else {
-            wrap.error = 1;
+pub unsafe extern "C" fn jq_sub_step(state: Wrap, input: Wrap, m: Wrap, inserts: Wrap) -> Wrap {
+    let state = &*state.json;
+    let (m, s) = (&*m.json, as_str(&*input.json));
+    let previous = count_at(state, "previous");
+    let offset = count_at(m, "offset");
+    let gap: String = s.chars().skip(previous).take(offset.saturating_sub(previous)).collect();
+    let mut result = match state.as_object().and_then(|o| o.get("result")) {
+        Some(Value::Array(r)) => r.clone(),
+        _ => Vec::new(),
+    };
+    if let Value::Array(inserts) = &*inserts.json {
+        for (i, insert) in inserts.iter().enumerate() {
+            let insert = match insert {
+                Value::String(insert) => insert,
+                v => return binop_error(&Value::String(gap), v, "cannot be added"),
+            };
+            if i == result.len() {
+                result.push(Value::String(String::new()));
+            }
+            if let Value::String(r) = &mut result[i] {
+                r.push_str(&gap);
+                r.push_str(insert);
+            }
         }
+    }
+    let previous = offset + count_at(m, "length");
+    ok(object(vec![
+        ("result", Value::Array(result)),
+        ("previous", Value::I64(previous as i64)),
+    ]))
+}
+
+#[used]
+static E_SUB_RESULTS: unsafe extern "C" fn(Wrap, Wrap) -> Wrap = jq_sub_results;
+/// The results of `sub` with the rest of the input appended, the input
+/// itself if nothing matched.
+#[no_mangle]
+pub unsafe extern "C" fn jq_sub_results(state: Wrap, input: Wrap) -> Wrap {
+    let state = &*state.json;
+    let s = as_str(&*input.json);
+    let rest: String = s.chars().skip(count_at(state, "previous")).collect();
+    match state.as_object().and_then(|o| o.get("result")) {
+        Some(Value::Array(r)) if !r.is_empty() => ok(Value::Array(
+            r.iter()
+                .map(|r| Value::String(format!("{}{}", as_str(r), rest)))
+                .collect(),
+        )),
+        _ => ok(Value::Array(vec![(*input.json).clone()])),
+    }
+}
+
+fn as_str(v: &Value) -> &str {
+    match v {
+        Value::String(s) => s,
+        _ => "",
+    }
+}
+
+/// The broken down time jq uses, `[year, month, day, hours, minutes,
+/// seconds, weekday, day of the year]` with months and days of the year
+/// counted from 0 and weekdays from Sunday.
+fn broken_down<Tz: TimeZone>(t: &DateTime<Tz>) -> Value {
+    let fields = [
+        f64::from(t.year()),
+        f64::from(t.month0()),
+        f64::from(t.day()),
+        f64::from(t.hour()),
+        f64::from(t.minute()),
+        f64::from(t.second()) + f64::from(t.nanosecond()) / 1e9,
+        f64::from(t.weekday().num_days_from_sunday()),
+        f64::from(t.ordinal0()),
+    ];
+    Value::Array(fields.iter().map(|f| number(*f)).collect())
+}
+
+/// The date and time a broken down time stands for, fields out of their
+/// range carry over into the next larger one like with `timegm`.
+fn from_broken_down(v: &Value, what: &str) -> Result<NaiveDateTime, String> {
+    let invalid = || format!("{} requires parsed datetime inputs", what);
+    let a = match v {
+        Value::Array(a) if a.len() >= 6 => a,
+        _ => return Err(invalid()),
+    };
+    let mut fields = [0.0; 6];
+    for (f, v) in fields.iter_mut().zip(a) {
+        *f = as_f64(v).ok_or_else(invalid)?;
+    }
+    let [year, month, day, hours, minutes, seconds] = fields;
+    let month = month.floor() as i64;
+    let year = year.floor() as i64 + month.div_euclid(12);
+    let seconds = hours.floor() * 3600.0 + minutes.floor() * 60.0 + seconds;
+    NaiveDate::from_ymd_opt(year as i32, month.rem_euclid(12) as u32 + 1, 1)
+        .map(|d| {
+            d.and_hms(0, 0, 0)
+                + Duration::days(day.floor() as i64 - 1)
+                + Duration::seconds(seconds.floor() as i64)
+                + Duration::nanoseconds(((seconds - seconds.floor()) * 1e9) as i64)
+        })
+        .ok_or_else(|| format!("{} got an invalid date", what))
+}
+
+/// The time `v` seconds after the epoch in `tz`.
+fn from_timestamp<Tz: TimeZone>(tz: &Tz, v: &Value, what: &str) -> Result<DateTime<Tz>, String> {
+    let secs = as_f64(v).ok_or_else(|| format!("{}() requires a number", what))?;
+    tz.timestamp_opt(secs.floor() as i64, ((secs - secs.floor()) * 1e9) as u32)
+        .single()
+        .ok_or_else(|| format!("{}() got a timestamp out of range", what))
+}
+
+/// Formats `t` with the C `strftime` style `format`.
+fn format_time<Tz: TimeZone>(t: &DateTime<Tz>, format: &Value, what: &str) -> Result<Value, String>
+where
+    Tz::Offset: std::fmt::Display,
+{
+    use std::fmt::Write;
+    let format = match format {
+        Value::String(f) => f,
+        _ => return Err(format!("{} requires a string format", what)),
+    };
+    let mut s = String::new();
+    match write!(s, "{}", t.format(format)) {
+        Ok(()) => Ok(Value::String(s)),
+        Err(_) => Err(format!("{}: invalid format \"{}\"", what, format)),
+    }
+}
+
+fn time_result(r: Result<Value, String>) -> Wrap {
+    match r {
+        Ok(v) => ok(v),
+        Err(e) => error(e),
+    }
+}
+
+#[used]
+static E_NOW: extern "C" fn(Wrap) -> Wrap = jq_now;
+#[no_mangle]
+pub extern "C" fn jq_now(_: Wrap) -> Wrap {
+    let t = Utc::now();
+    ok(Value::F64(
+        t.timestamp() as f64 + f64::from(t.timestamp_subsec_nanos()) / 1e9,
+    ))
+}
+
+#[used]
+static E_MKTIME: unsafe extern "C" fn(Wrap) -> Wrap = jq_mktime;
+/// The seconds since the epoch of a broken down time in UTC.
+#[no_mangle]
+pub unsafe extern "C" fn jq_mktime(wrap: Wrap) -> Wrap {
+    time_result(
+        from_broken_down(&*wrap.json, "mktime")
+            .map(|t| Value::I64(Utc.from_utc_datetime(&t).timestamp())),
+    )
+}
+
+#[used]
+static E_GMTIME: unsafe extern "C" fn(Wrap) -> Wrap = jq_gmtime;
+#[no_mangle]
+pub unsafe extern "C" fn jq_gmtime(wrap: Wrap) -> Wrap {
+    time_result(from_timestamp(&Utc, &*wrap.json, "gmtime").map(|t| broken_down(&t)))
+}
+
+#[used]
+static E_LOCALTIME: unsafe extern "C" fn(Wrap) -> Wrap = jq_localtime;
+#[no_mangle]
+pub unsafe extern "C" fn jq_localtime(wrap: Wrap) -> Wrap {
+    time_result(from_timestamp(&Local, &*wrap.json, "localtime").map(|t| broken_down(&t)))
+}
+
+#[used]
+static E_STRFTIME: unsafe extern "C" fn(Wrap, Wrap) -> Wrap = jq_strftime;
+/// Formats a broken down time in UTC or seconds since the epoch.
+#[no_mangle]
+pub unsafe extern "C" fn jq_strftime(wrap: Wrap, format: Wrap) -> Wrap {
+    let what = "strftime/1";
+    let t = match &*wrap.json {
+        Value::Array(_) => from_broken_down(&*wrap.json, what).map(|t| Utc.from_utc_datetime(&t)),
+        v if as_f64(v).is_some() => from_timestamp(&Utc, v, "gmtime"),
+        _ => Err(format!("{} requires parsed datetime inputs", what)),
+    };
+    time_result(t.and_then(|t| format_time(&t, &*format.json, what)))
+}
+
+#[used]
+static E_STRFLOCALTIME: unsafe extern "C" fn(Wrap, Wrap) -> Wrap = jq_strflocaltime;
+/// Formats a broken down time in the local timezone or seconds since the
+/// epoch.
+#[no_mangle]
+pub unsafe extern "C" fn jq_strflocaltime(wrap: Wrap, format: Wrap) -> Wrap {
+    let what = "strflocaltime/1";
+    let t = match &*wrap.json {
+        Value::Array(_) => from_broken_down(&*wrap.json, what).and_then(|t| {
+            Local
+                .from_local_datetime(&t)
+                .earliest()
+                .ok_or_else(|| format!("{} got a time skipped in the local timezone", what))
+        }),
+        v if as_f64(v).is_some() => from_timestamp(&Local, v, "localtime"),
+        _ => Err(format!("{} requires parsed datetime inputs", what)),
+    };
+    time_result(t.and_then(|t| format_time(&t, &*format.json, what)))
+}
+
+#[used]
+static E_STRPTIME: unsafe extern "C" fn(Wrap, Wrap) -> Wrap = jq_strptime;
+/// Parses a date with the C `strptime` style `format` into a broken down
+/// time in UTC, times with an offset are converted, missing times are
+/// midnight.
+#[no_mangle]
+pub unsafe extern "C" fn jq_strptime(wrap: Wrap, format: Wrap) -> Wrap {
+    let (s, format) = match (&*wrap.json, &*format.json) {
+        (Value::String(s), Value::String(f)) => (s, f),
+        _ => return error("strptime/1 requires string inputs and arguments".to_string()),
+    };
+    let t = DateTime::parse_from_str(s, format)
+        .map(|t| t.naive_utc())
+        .or_else(|_| NaiveDateTime::parse_from_str(s, format))
+        .or_else(|_| NaiveDate::parse_from_str(s, format).map(|d| d.and_hms(0, 0, 0)));
+    match t {
+        Ok(t) => ok(broken_down(&Utc.from_utc_datetime(&t))),
+        Err(_) => error(format!(
+            "date \"{}\" does not match format \"{}\"",
+            s, format
+        )),
+    }
+}
+
+#[used]
+static E_NUMBER_CHECK: unsafe extern "C" fn(Wrap) -> Wrap = jq_number_check;
+/// Fails unless the input is a number, math functions take their arguments
+/// through `jq_to_f64` after it.
+#[no_mangle]
+pub unsafe extern "C" fn jq_number_check(wrap: Wrap) -> Wrap {
+    match &*wrap.json {
+        Value::I64(_) | Value::F64(_) => wrap,
+        v => error(format!("{} ({}) number required", type_name(v), dump_trunc(v))),
+    }
+}
+
+#[used]
+static E_TO_F64: unsafe extern "C" fn(Wrap) -> f64 = jq_to_f64;
+#[no_mangle]
+pub unsafe extern "C" fn jq_to_f64(wrap: Wrap) -> f64 {
+    as_f64(&*wrap.json).unwrap_or(std::f64::NAN)
+}
+
+#[used]
+static E_FROM_F64: extern "C" fn(f64) -> Wrap = jq_from_f64;
+#[no_mangle]
+pub extern "C" fn jq_from_f64(x: f64) -> Wrap {
+    ok(number(x))
+}
+
+/// The mantissa in `[0.5, 1)` and the exponent of `x`, `x` is equal to
+/// `mantissa * 2^exponent`.
+fn frexp(x: f64) -> (f64, i64) {
+    if x == 0.0 || !x.is_finite() {
+        return (x, 0);
+    }
+    let bits = x.to_bits();
+    let exp = ((bits >> 52) & 0x7ff) as i64;
+    if exp == 0 {
+        // subnormals are scaled into the normal range first
+        let scaled = x * 2f64.powi(64);
+        let exp = ((scaled.to_bits() >> 52) & 0x7ff) as i64;
+        let m = f64::from_bits((scaled.to_bits() & !(0x7ff << 52)) | (1022 << 52));
+        (m, exp - 1022 - 64)
     } else {
-        wrap.error = 2;
+        (f64::from_bits((bits & !(0x7ff << 52)) | (1022 << 52)), exp - 1022)
     }
-    wrap
 }
 
 #[used]
-static E_GET_IDX: unsafe extern "C" fn(Wrap, usize) -> Wrap = jq_get_idx;
+static E_FREXP: extern "C" fn(f64) -> Wrap = jq_frexp;
+/// `[mantissa, exponent]` as `frexp` splits `x`.
+#[no_mangle]
+pub extern "C" fn jq_frexp(x: f64) -> Wrap {
+    let (m, e) = frexp(x);
+    ok(Value::Array(vec![number(m), Value::I64(e)]))
+}
+
+#[used]
+static E_SIGNIFICAND: extern "C" fn(f64) -> f64 = jq_significand;
+/// The mantissa of `x` in `[1, 2)`, zeros, infinities and NaN are returned
+/// as they are.
+#[no_mangle]
+pub extern "C" fn jq_significand(x: f64) -> f64 {
+    if x == 0.0 || !x.is_finite() {
+        x
+    } else {
+        frexp(x).0 * 2.0
+    }
+}
+
+#[used]
+static E_DREM: extern "C" fn(f64, f64) -> f64 = jq_drem;
+/// The IEEE remainder of `x / y`, `x - n * y` with `n` the quotient rounded
+/// to the nearest integer, ties to even.
+#[no_mangle]
+pub extern "C" fn jq_drem(x: f64, y: f64) -> f64 {
+    if x.is_nan() || y.is_nan() || x.is_infinite() || y == 0.0 {
+        return std::f64::NAN;
+    }
+    if y.is_infinite() {
+        return x;
+    }
+    let y = y.abs();
+    // `%` is exact, modulo `2y` the parity of the quotient is left
+    let mut r = if y <= std::f64::MAX / 2.0 {
+        x.abs() % (2.0 * y)
+    } else {
+        x.abs()
+    };
+    let odd = r >= y;
+    if odd {
+        r -= y;
+    }
+    let half = if y < 2.0 * std::f64::MIN_POSITIVE {
+        (2.0 * r).partial_cmp(&y)
+    } else {
+        r.partial_cmp(&(0.5 * y))
+    };
+    if half == Some(Ordering::Greater) || (half == Some(Ordering::Equal) && odd) {
+        r -= y;
+    }
+    if x.is_sign_negative() {
+        -r
+    } else {
+        r
+    }
+}
+
+#[used]
+static E_MODF: extern "C" fn(f64) -> Wrap = jq_modf;
+/// `[fraction, integer part]`, both with the sign of `x`.
+#[no_mangle]
+pub extern "C" fn jq_modf(x: f64) -> Wrap {
+    let int = if x.is_infinite() { x } else { x.trunc() };
+    let fract = if x.is_infinite() { 0f64.copysign(x) } else { x - int };
+    ok(Value::Array(vec![number(fract), number(int)]))
+}
+
+#[used]
+static E_ISINFINITE: extern "C" fn(f64) -> i64 = jq_isinfinite;
+#[no_mangle]
+pub extern "C" fn jq_isinfinite(x: f64) -> i64 {
+    x.is_infinite() as i64
+}
+
+#[used]
+static E_ISNAN: extern "C" fn(f64) -> i64 = jq_isnan;
+#[no_mangle]
+pub extern "C" fn jq_isnan(x: f64) -> i64 {
+    x.is_nan() as i64
+}
+
+#[used]
+static E_ISNORMAL: extern "C" fn(f64) -> i64 = jq_isnormal;
 #[no_mangle]
-pub unsafe extern "C" fn jq_get_idx(mut wrap: Wrap, idx: usize) -> Wrap {
-    if let Some(o) = (&*wrap.json).as_array() {
-        if let Some(v) = o.get(idx) {
-            wrap.json = v;
+pub extern "C" fn jq_isnormal(x: f64) -> i64 {
+    x.is_normal() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn json(s: &str) -> Value {
+        simd_json::to_owned_value(&mut s.as_bytes().to_vec()).unwrap()
+    }
+
+    fn at(json: *const Value) -> Wrap {
+        Wrap { error: 0, json }
+    }
+
+    fn wrap(s: &str) -> Wrap {
+        at(alloc(json(s)))
+    }
+
+    /// The value `w` holds, or its error.
+    fn result(w: Wrap) -> Result<Value, Value> {
+        let v = unsafe { (*w.json).clone() };
+        if w.error == 0 {
+            Ok(v)
         } else {
-            wrap.error = 1;
+            Err(v)
+        }
+    }
+
+    fn message(s: &str) -> Result<Value, Value> {
+        Err(Value::String(s.to_string()))
+    }
+
+    #[test]
+    fn iterates_arrays_and_objects() {
+        unsafe {
+            let a = jq_iter_check(wrap("[1, [2]]")).json;
+            assert_eq!(jq_iter_len(at(a)), 2);
+            assert_eq!(result(jq_iter_get(at(a), 1)), Ok(json("[2]")));
+
+            let o = jq_iter_check(wrap(r#"{"a": 1, "b": 2, "c": 3}"#)).json;
+            let mut values: Vec<_> = (0..jq_iter_len(at(o)))
+                .map(|i| result(jq_iter_get(at(o), i)).unwrap())
+                .collect();
+            values.sort_by_key(|v| v.to_string());
+            assert_eq!(values, vec![json("1"), json("2"), json("3")]);
+        }
+    }
+
+    #[test]
+    fn iterating_scalars_fails() {
+        unsafe {
+            assert_eq!(
+                result(jq_iter_check(wrap("1"))),
+                message("Cannot iterate over number (1)")
+            );
+            assert_eq!(
+                result(jq_iter_check(wrap("null"))),
+                message("Cannot iterate over null (null)")
+            );
+        }
+    }
+
+    fn get_key(w: Wrap, key: &str) -> Result<Value, Value> {
+        result(unsafe { jq_get_key(w, key.as_ptr(), key.len()) })
+    }
+
+    #[test]
+    fn missing_keys_and_indices_are_null() {
+        unsafe {
+            assert_eq!(get_key(wrap(r#"{"a": 1}"#), "a"), Ok(json("1")));
+            assert_eq!(get_key(wrap(r#"{"a": 1}"#), "b"), Ok(Value::Null));
+            assert_eq!(get_key(wrap("null"), "a"), Ok(Value::Null));
+            assert_eq!(result(jq_get_idx(wrap("[1, 2]"), -1)), Ok(json("2")));
+            assert_eq!(result(jq_get_idx(wrap("[1, 2]"), 2)), Ok(Value::Null));
+            assert_eq!(result(jq_get_idx(wrap("[1, 2]"), -3)), Ok(Value::Null));
+            assert_eq!(result(jq_get_idx(wrap("null"), 0)), Ok(Value::Null));
+        }
+    }
+
+    #[test]
+    fn indexing_reports_jq_errors() {
+        unsafe {
+            assert_eq!(
+                get_key(wrap("[1]"), "a"),
+                message(r#"Cannot index array with "a""#)
+            );
+            assert_eq!(
+                result(jq_get_idx(wrap(r#"{"a": 1}"#), 0)),
+                message("Cannot index object with number")
+            );
+        }
+    }
+
+    #[test]
+    fn error_and_catch_flip_the_error_flag() {
+        let e = jq_error(wrap(r#""boom""#));
+        assert_eq!(e.error, 1);
+        assert_eq!(result(jq_catch(e)), Ok(json(r#""boom""#)));
+    }
+
+    #[test]
+    fn slices_clamp_their_bounds() {
+        let slice = |s: &str, from, to| result(unsafe { jq_slice(wrap(s), from, to) });
+        assert_eq!(slice("[0, 1, 2, 3]", 1, 3), Ok(json("[1, 2]")));
+        assert_eq!(slice("[0, 1, 2, 3]", -2, std::i64::MAX), Ok(json("[2, 3]")));
+        assert_eq!(slice("[0, 1, 2, 3]", -10, 2), Ok(json("[0, 1]")));
+        assert_eq!(slice("[0, 1, 2, 3]", 3, 1), Ok(json("[]")));
+        assert_eq!(slice(r#""aéiou""#, 1, -1), Ok(json(r#""éio""#)));
+        assert_eq!(slice("null", 1, 2), Ok(Value::Null));
+        assert_eq!(slice("1", 1, 2), message("Cannot index number with object"));
+    }
+
+    #[test]
+    fn objects_are_built_in_place() {
+        unsafe {
+            let o = jq_object_new();
+            let p = o.json;
+            let o = jq_object_insert(o, wrap(r#""a""#), wrap("1"));
+            let o = jq_object_insert(o, wrap(r#""b""#), wrap("[2]"));
+            assert_eq!(o.json, p);
+            assert_eq!(result(o), Ok(json(r#"{"a": 1, "b": [2]}"#)));
+            assert_eq!(
+                result(jq_object_insert(jq_object_new(), wrap("1"), wrap("1"))),
+                message("Object keys must be strings")
+            );
+        }
+    }
+
+    #[test]
+    fn arrays_collect_values() {
+        unsafe {
+            let a = jq_array_new();
+            jq_array_push(at(a.json), wrap(r#"{"a": 1}"#));
+            jq_array_push(at(a.json), wrap("2"));
+            assert_eq!(result(a), Ok(json(r#"[{"a": 1}, 2]"#)));
+        }
+    }
+
+    #[test]
+    fn total_order_of_values() {
+        let sorted = [
+            "null",
+            "false",
+            "true",
+            "-1",
+            "1.5",
+            "2",
+            r#""""#,
+            r#""a""#,
+            "[]",
+            "[1]",
+            "[1, 0]",
+            "{}",
+            r#"{"a": 2}"#,
+            r#"{"a": 1, "b": 0}"#,
+            r#"{"b": 0}"#,
+        ];
+        for (i, a) in sorted.iter().enumerate() {
+            for (j, b) in sorted.iter().enumerate() {
+                let expected = i.cmp(&j) as i64;
+                assert_eq!(
+                    unsafe { jq_compare(wrap(a), wrap(b)) },
+                    expected,
+                    "{} {}",
+                    a,
+                    b
+                );
+            }
+        }
+        assert_eq!(cmp_values(&json("1"), &json("1.0")), Ordering::Equal);
+    }
+
+    #[test]
+    fn nan_sorts_below_numbers_and_equals_nothing() {
+        let nan = Value::F64(std::f64::NAN);
+        assert_eq!(cmp_values(&nan, &nan), Ordering::Less);
+        assert_eq!(cmp_values(&nan, &json("-1e300")), Ordering::Less);
+        assert_eq!(cmp_values(&json("-1e300"), &nan), Ordering::Greater);
+        assert_eq!(cmp_values(&json("null"), &nan), Ordering::Less);
+        assert_eq!(cmp_values(&json(r#""a""#), &nan), Ordering::Greater);
+        assert_ne!(
+            cmp_values(&Value::Array(vec![nan.clone()]), &Value::Array(vec![nan])),
+            Ordering::Equal
+        );
+    }
+
+    #[test]
+    fn only_false_and_null_are_falsy() {
+        for (v, truthy) in &[
+            ("null", 0),
+            ("false", 0),
+            ("true", 1),
+            ("0", 1),
+            (r#""""#, 1),
+            ("[]", 1),
+            ("{}", 1),
+        ] {
+            assert_eq!(unsafe { jq_truthy(wrap(v)) }, *truthy, "{}", v);
+        }
+    }
+
+    #[test]
+    fn arithmetic_on_json_values() {
+        let op = |f: unsafe extern "C" fn(Wrap, Wrap) -> Wrap, a: &str, b: &str| {
+            result(unsafe { f(wrap(a), wrap(b)) })
+        };
+        assert_eq!(op(jq_add, "1", "2.5"), Ok(json("3.5")));
+        assert_eq!(op(jq_add, "null", "[1]"), Ok(json("[1]")));
+        assert_eq!(op(jq_add, r#""a""#, r#""b""#), Ok(json(r#""ab""#)));
+        assert_eq!(
+            op(jq_add, r#"{"a": 1}"#, r#"{"a": 2, "b": 3}"#),
+            Ok(json(r#"{"a": 2, "b": 3}"#))
+        );
+        assert_eq!(op(jq_sub, "[1, 2, 1, 3]", "[1]"), Ok(json("[2, 3]")));
+        assert_eq!(op(jq_mul, r#""ab""#, "3"), Ok(json(r#""ababab""#)));
+        assert_eq!(op(jq_mul, "0", r#""ab""#), Ok(Value::Null));
+        assert_eq!(
+            op(jq_mul, r#"{"a": {"b": 1}}"#, r#"{"a": {"c": 2}}"#),
+            Ok(json(r#"{"a": {"b": 1, "c": 2}}"#))
+        );
+        assert_eq!(op(jq_div, r#""a,b""#, r#"",""#), Ok(json(r#"["a", "b"]"#)));
+        assert_eq!(op(jq_div, "1", "4"), Ok(json("0.25")));
+        assert_eq!(op(jq_mod, "7", "-3"), Ok(json("1")));
+        assert_eq!(op(jq_mod, "-7", "3"), Ok(json("-1")));
+        assert_eq!(
+            op(jq_div, "1", "0"),
+            message("number (1) and number (0) cannot be divided because the divisor is zero")
+        );
+        assert_eq!(
+            op(jq_sub, r#""a""#, "1"),
+            message(r#"string ("a") and number (1) cannot be subtracted"#)
+        );
+    }
+
+    #[test]
+    fn string_repetition_is_bounded() {
+        let mul = |a: &str, b: &str| result(unsafe { jq_mul(wrap(a), wrap(b)) });
+        assert_eq!(
+            mul(r#""x""#, "1e18"),
+            message("Repeat string result too long")
+        );
+        assert_eq!(
+            mul("1e300", r#""xy""#),
+            message("Repeat string result too long")
+        );
+        assert_eq!(mul(r#""""#, "1e18"), Ok(json(r#""""#)));
+    }
+
+    #[test]
+    fn limit_counts_like_jq() {
+        let limit = |n: &str| unsafe { jq_limit(wrap(n)) };
+        assert_eq!(limit("3"), 3);
+        assert_eq!(limit("1.5"), 2);
+        assert_eq!(limit("0"), 0);
+        assert_eq!(limit("-1"), std::i64::MAX);
+        assert_eq!(limit(r#""a""#), std::i64::MAX);
+    }
+
+    #[test]
+    fn index_with_runtime_keys() {
+        let index = |v: &str, k: &str| result(unsafe { jq_index(wrap(v), wrap(k)) });
+        assert_eq!(index(r#"{"a": 1}"#, r#""a""#), Ok(json("1")));
+        assert_eq!(index("[1, 2, 3]", "-1"), Ok(json("3")));
+        assert_eq!(index("[1, 2, 3]", "1.7"), Ok(json("2")));
+        assert_eq!(index("null", "true"), Ok(Value::Null));
+        assert_eq!(
+            index("[1]", "true"),
+            message("Cannot index array with boolean")
+        );
+    }
+
+    #[test]
+    fn prelude_parses() {
+        let (rest, defs) = funcdefs(PRELUDE).unwrap();
+        assert_eq!(rest.trim(), "");
+        let recurse: Vec<_> = defs
+            .iter()
+            .filter(|d| d.name == "recurse")
+            .map(|d| d.params.len())
+            .collect();
+        assert_eq!(recurse, vec![1, 2, 0]);
+    }
+
+    #[test]
+    fn formats_values() {
+        let format = |v: &str, f: &str| {
+            result(unsafe { jq_format(wrap(v), at(alloc(Value::String(f.to_string())))) })
+        };
+        let string = |s: &str| Ok(Value::String(s.to_string()));
+        assert_eq!(format(r#"[1, "a"]"#, "text"), string(r#"[1,"a"]"#));
+        assert_eq!(format(r#""a""#, "text"), string("a"));
+        assert_eq!(format(r#""a""#, "json"), string(r#""a""#));
+        assert_eq!(
+            format(r#""<&'\"x>""#, "html"),
+            string("&lt;&amp;&#39;&quot;x&gt;")
+        );
+        assert_eq!(format(r#""a b/ü""#, "uri"), string("a%20b%2F%C3%BC"));
+        assert_eq!(
+            format(r#"[1, "a\"b", null, true]"#, "csv"),
+            string(r#"1,"a""b",,true"#)
+        );
+        assert_eq!(format(r#"["a\tb", 1]"#, "tsv"), string("a\\tb\t1"));
+        assert_eq!(format(r#"["it's", 1]"#, "sh"), string("'it'\\''s' 1"));
+        assert_eq!(format(r#""hello""#, "base64"), string("aGVsbG8="));
+        assert_eq!(format(r#""aGVsbG8=""#, "base64d"), string("hello"));
+        assert_eq!(format(r#""hello""#, "base32"), string("NBSWY3DP"));
+        assert_eq!(format(r#""NBSWY3DP""#, "base32d"), string("hello"));
+        assert_eq!(
+            format("{}", "csv"),
+            message("object ({}) cannot be csv-formatted, only an array can be")
+        );
+        assert_eq!(
+            format("[[1]]", "tsv"),
+            message("array ([1]) is not valid in a tsv row")
+        );
+        assert_eq!(
+            format("{}", "sh"),
+            message("object ({}) can not be escaped for shell")
+        );
+    }
+
+    #[test]
+    fn getpath_reads_through_null() {
+        let getpath = |v: &str, p: &str| result(unsafe { jq_getpath(wrap(v), wrap(p)) });
+        assert_eq!(getpath(r#"{"a": [1, 2]}"#, r#"["a", -1]"#), Ok(json("2")));
+        assert_eq!(getpath("null", r#"["a", 0]"#), Ok(Value::Null));
+        assert_eq!(
+            getpath("[1, 2, 3]", r#"[{"start": 1, "end": 2}]"#),
+            Ok(json("[2]"))
+        );
+        assert_eq!(
+            getpath(r#"{"a": 1}"#, r#"["a", "b"]"#),
+            message(r#"Cannot index number with "b""#)
+        );
+        assert_eq!(
+            getpath("{}", r#""a""#),
+            message("Path must be specified as an array")
+        );
+    }
+
+    #[test]
+    fn setpath_creates_missing_containers() {
+        let setpath =
+            |v: &str, p: &str, x: &str| result(unsafe { jq_setpath(wrap(v), wrap(p), wrap(x)) });
+        assert_eq!(
+            setpath("null", r#"["a", 1]"#, "2"),
+            Ok(json(r#"{"a": [null, 2]}"#))
+        );
+        assert_eq!(setpath("[1, 2]", "[-1]", "3"), Ok(json("[1, 3]")));
+        assert_eq!(setpath("1", "[]", "2"), Ok(json("2")));
+        assert_eq!(
+            setpath("[1, 2, 3]", r#"[{"start": 1, "end": null}]"#, "[9]"),
+            Ok(json("[1, 9]"))
+        );
+        assert_eq!(
+            setpath("[1, 2]", "[-3]", "3"),
+            message("Out of bounds negative array index")
+        );
+        assert_eq!(
+            setpath("[1]", r#"[{"start": 0}]"#, "1"),
+            message("A slice of an array can only be assigned another array")
+        );
+        assert_eq!(
+            setpath(r#"{"a": 1}"#, "[0]", "1"),
+            message("Cannot index object with number")
+        );
+    }
+
+    #[test]
+    fn delpaths_deletes_from_the_end() {
+        let delpaths = |v: &str, p: &str| result(unsafe { jq_delpaths(wrap(v), wrap(p)) });
+        assert_eq!(delpaths("[1, 2, 3]", "[[0], [1]]"), Ok(json("[3]")));
+        assert_eq!(
+            delpaths(r#"{"a": {"b": 1, "c": 2}}"#, r#"[["a", "b"]]"#),
+            Ok(json(r#"{"a": {"c": 2}}"#))
+        );
+        assert_eq!(
+            delpaths(r#"{"a": 1}"#, r#"[["x", "y"]]"#),
+            Ok(json(r#"{"a": 1}"#))
+        );
+        assert_eq!(delpaths("[1]", "[[]]"), Ok(Value::Null));
+        assert_eq!(
+            delpaths("1", "[[0]]"),
+            message("Cannot delete field at index of number")
+        );
+        assert_eq!(
+            delpaths("[1]", "[0]"),
+            message("Path must be specified as an array")
+        );
+    }
+
+    #[test]
+    fn paths_are_extended_by_copy() {
+        unsafe {
+            let path = wrap(r#"["a"]"#);
+            let longer = jq_path_append(at(path.json), wrap("0"));
+            assert_eq!(result(longer), Ok(json(r#"["a", 0]"#)));
+            assert_eq!(result(path), Ok(json(r#"["a"]"#)));
+            assert_eq!(
+                result(jq_invalid_path(wrap("1"))),
+                message("Invalid path expression with result 1")
+            );
+        }
+    }
+
+    #[test]
+    fn entries_of_objects_and_arrays() {
+        let to_entries = |v: &str| result(unsafe { jq_to_entries(wrap(v)) });
+        assert_eq!(
+            to_entries(r#"{"a": 1}"#),
+            Ok(json(r#"[{"key": "a", "value": 1}]"#))
+        );
+        assert_eq!(
+            to_entries("[true]"),
+            Ok(json(r#"[{"key": 0, "value": true}]"#))
+        );
+        assert_eq!(to_entries("1"), message("number (1) has no keys"));
+
+        let from_entries = |v: &str| result(unsafe { jq_from_entries(wrap(v)) });
+        assert_eq!(
+            from_entries(
+                r#"[{"key": "a", "value": 1}, {"k": false, "name": "b", "v": 2}, {"key": 3}]"#
+            ),
+            Ok(json(r#"{"a": 1, "b": 2, "3": null}"#))
+        );
+        assert_eq!(
+            from_entries(r#"[{"value": 1}]"#),
+            Ok(json(r#"{"null": 1}"#))
+        );
+        assert_eq!(
+            from_entries("[null]"),
+            message("Cannot check whether null has a string key")
+        );
+        assert_eq!(
+            from_entries("[1]"),
+            message(r#"Cannot index number with "key""#)
+        );
+        assert_eq!(from_entries("1"), message("Cannot iterate over number (1)"));
+    }
+
+    #[test]
+    fn named_groups_use_the_regex_crate_syntax() {
+        assert_eq!(named_groups(r"(?<y>\d+)"), r"(?P<y>\d+)");
+        assert_eq!(named_groups(r"(?<=a)(?<!b)"), r"(?<=a)(?<!b)");
+        assert_eq!(named_groups(r"\(?<x>é(?<z>)"), r"\(?<x>é(?P<z>)");
+    }
+
+    fn regex(input: &str, re: &str, flags: &str, mode: i64) -> Result<Value, Value> {
+        result(unsafe { jq_match(wrap(input), 0, wrap(re), wrap(flags), mode) })
+    }
+
+    #[test]
+    fn regexes_test_and_match() {
+        assert_eq!(
+            regex(r#""abc""#, r#""B""#, r#""i""#, REGEX_TEST),
+            Ok(json("true"))
+        );
+        assert_eq!(
+            regex(r#""abc""#, r#""B""#, "null", REGEX_TEST),
+            Ok(json("false"))
+        );
+        assert_eq!(
+            regex(r#""abc""#, r#""x*""#, r#""n""#, REGEX_TEST),
+            Ok(json("false"))
+        );
+        assert_eq!(
+            regex(
+                r#""xé ab""#,
+                r#""(?<w>[a-z]+)(\\d)?""#,
+                r#""g""#,
+                REGEX_MATCH
+            ),
+            Ok(json(
+                r#"[
+                    {"offset": 0, "length": 1, "string": "x", "captures": [
+                        {"offset": 0, "length": 1, "string": "x", "name": "w"},
+                        {"offset": -1, "length": 0, "string": null, "name": null}
+                    ]},
+                    {"offset": 3, "length": 2, "string": "ab", "captures": [
+                        {"offset": 3, "length": 2, "string": "ab", "name": "w"},
+                        {"offset": -1, "length": 0, "string": null, "name": null}
+                    ]}
+                ]"#
+            ))
+        );
+        assert_eq!(
+            regex(r#""a1b2""#, r#"["(?<d>\\d)", null]"#, "null", REGEX_CAPTURE),
+            Ok(json(r#"[{"d": "1"}]"#))
+        );
+        assert_eq!(
+            regex(r#""a1b2""#, r#""[a-z](\\d)""#, "null", REGEX_SCAN),
+            Ok(json(r#"[["1"], ["2"]]"#))
+        );
+        assert_eq!(
+            regex(r#""a1b2""#, r#""\\d""#, "null", REGEX_SCAN),
+            Ok(json(r#"["1", "2"]"#))
+        );
+        assert_eq!(
+            regex(r#""a, b,c""#, r#"", *""#, "null", REGEX_SPLIT),
+            Ok(json(r#"["a", "b", "c"]"#))
+        );
+        assert_eq!(
+            regex("1", r#""a""#, "null", REGEX_TEST),
+            message("number (1) cannot be matched, as it is not a string")
+        );
+        assert_eq!(
+            regex(r#""a""#, "1", "null", REGEX_TEST),
+            message("number (1) cannot be matched, as it is not a string")
+        );
+        assert_eq!(
+            regex(r#""a""#, r#""a""#, r#""q""#, REGEX_TEST),
+            message("q is not a valid modifier string")
+        );
+        assert!(regex(r#""a""#, r#""(""#, "null", REGEX_TEST).is_err());
+    }
+
+    #[test]
+    fn sub_collects_the_results() {
+        unsafe {
+            let input = wrap(r#""abcb""#);
+            let step = |state: Wrap, m: &str, inserts: &str| {
+                jq_sub_step(state, at(input.json), wrap(m), wrap(inserts))
+            };
+            let start = wrap("{}");
+            let state = step(start, r#"{"offset": 1, "length": 1}"#, r#"["X", "Y"]"#);
+            let state = step(state, r#"{"offset": 3, "length": 1}"#, r#"["Z"]"#);
+            assert_eq!(
+                result(jq_sub_results(state, at(input.json))),
+                Ok(json(r#"["aXcZ", "aY"]"#))
+            );
+            let state = step(wrap("{}"), r#"{"offset": 1, "length": 1}"#, r#"["X"]"#);
+            assert_eq!(
+                result(jq_sub_results(state, at(input.json))),
+                Ok(json(r#"["aXcb"]"#))
+            );
+            assert_eq!(
+                result(jq_sub_results(wrap("{}"), at(input.json))),
+                Ok(json(r#"["abcb"]"#))
+            );
+            assert_eq!(
+                result(step(wrap("{}"), r#"{"offset": 1, "length": 1}"#, "[1]")),
+                message(r#"string ("a") and number (1) cannot be added"#)
+            );
+        }
+    }
+
+    const BROKEN_DOWN: &str = "[2015, 2, 5, 23, 51, 47, 4, 63]";
+
+    #[test]
+    fn broken_down_times() {
+        unsafe {
+            assert_eq!(result(jq_gmtime(wrap("1425599507"))), Ok(json(BROKEN_DOWN)));
+            assert_eq!(
+                result(jq_gmtime(wrap("1425599507.5"))),
+                Ok(json("[2015, 2, 5, 23, 51, 47.5, 4, 63]"))
+            );
+            assert_eq!(result(jq_mktime(wrap(BROKEN_DOWN))), Ok(json("1425599507")));
+            // fields out of range carry over
+            assert_eq!(
+                result(jq_mktime(wrap("[2015, 12, 1, 0, 0, 0]"))),
+                Ok(json("1451606400"))
+            );
+            assert_eq!(
+                result(jq_mktime(wrap("[2015, 0, 0, 0, 0, 0]"))),
+                Ok(json("1419984000"))
+            );
+            assert_eq!(
+                result(jq_mktime(wrap(r#""x""#))),
+                message("mktime requires parsed datetime inputs")
+            );
+            assert_eq!(
+                result(jq_gmtime(wrap(r#""x""#))),
+                message("gmtime() requires a number")
+            );
+        }
+    }
+
+    #[test]
+    fn times_are_formatted_and_parsed() {
+        let strftime = |v: &str, f: &str| result(unsafe { jq_strftime(wrap(v), wrap(f)) });
+        let string = |s: &str| Ok(Value::String(s.to_string()));
+        assert_eq!(
+            strftime(BROKEN_DOWN, r#""%Y-%m-%dT%H:%M:%SZ""#),
+            string("2015-03-05T23:51:47Z")
+        );
+        assert_eq!(
+            strftime("1425599507", r#""%A, %B %d, %Y""#),
+            string("Thursday, March 05, 2015")
+        );
+        assert_eq!(
+            strftime(r#""x""#, r#""%Y""#),
+            message("strftime/1 requires parsed datetime inputs")
+        );
+        assert_eq!(
+            strftime("1", "1"),
+            message("strftime/1 requires a string format")
+        );
+
+        let strptime = |v: &str, f: &str| result(unsafe { jq_strptime(wrap(v), wrap(f)) });
+        assert_eq!(
+            strptime(r#""2015-03-05T23:51:47Z""#, r#""%Y-%m-%dT%H:%M:%SZ""#),
+            Ok(json(BROKEN_DOWN))
+        );
+        assert_eq!(
+            strptime(
+                r#""2015-03-06 00:51:47 +0100""#,
+                r#""%Y-%m-%d %H:%M:%S %z""#
+            ),
+            Ok(json(BROKEN_DOWN))
+        );
+        assert_eq!(
+            strptime(r#""2015-03-05""#, r#""%Y-%m-%d""#),
+            Ok(json("[2015, 2, 5, 0, 0, 0, 4, 63]"))
+        );
+        assert_eq!(
+            strptime(r#""5.3.2015""#, r#""%Y-%m-%d""#),
+            message(r#"date "5.3.2015" does not match format "%Y-%m-%d""#)
+        );
+        assert_eq!(
+            strptime("1", r#""%Y""#),
+            message("strptime/1 requires string inputs and arguments")
+        );
+    }
+
+    #[test]
+    fn non_finite_numbers_print_like_jq() {
+        let v = Value::Array(vec![
+            Value::F64(std::f64::INFINITY),
+            Value::F64(std::f64::NEG_INFINITY),
+            Value::F64(std::f64::NAN),
+            Value::F64(1.5),
+            Value::String("a".to_string()),
+        ]);
+        assert_eq!(
+            dump(&v),
+            r#"[1.7976931348623157e+308,-1.7976931348623157e+308,null,1.5,"a"]"#
+        );
+        assert_eq!(dump(&json(r#"{"a": [1, {}]}"#)), r#"{"a":[1,{}]}"#);
+        let string = |s: &str| Ok(Value::String(s.to_string()));
+        unsafe {
+            let csv = at(alloc(Value::String("csv".to_string())));
+            let row = at(alloc(Value::Array(vec![Value::F64(
+                std::f64::NEG_INFINITY,
+            )])));
+            assert_eq!(
+                result(jq_format(row, csv)),
+                string("-1.7976931348623157e+308")
+            );
+        }
+    }
+
+    #[test]
+    fn negative_zero_keeps_its_sign() {
+        match number(-0.0) {
+            Value::F64(z) => assert!(z == 0.0 && z.is_sign_negative()),
+            v => panic!("-0 became {:?}", v),
+        }
+        assert_eq!(number(0.0), Value::I64(0));
+        assert_eq!(number(-3.0), Value::I64(-3));
+        assert_eq!(number(0.5), Value::F64(0.5));
+    }
+
+    #[test]
+    fn math_functions_libm_may_lack() {
+        assert_eq!(jq_significand(12.0), 1.5);
+        assert_eq!(jq_significand(-0.375), -1.5);
+        assert_eq!(jq_significand(5e-324), 1.0);
+        assert!(jq_significand(std::f64::NAN).is_nan());
+        assert_eq!(jq_significand(std::f64::INFINITY), std::f64::INFINITY);
+        assert_eq!(jq_drem(10.0, 3.0), 1.0);
+        assert_eq!(jq_drem(11.0, 3.0), -1.0);
+        assert_eq!(jq_drem(5.0, 2.0), 1.0);
+        assert_eq!(jq_drem(7.0, 2.0), -1.0);
+        assert_eq!(jq_drem(-7.0, 2.0), 1.0);
+        assert_eq!(jq_drem(1e300, 3.5e10), -15599459840.0);
+        assert_eq!(jq_drem(1.0, std::f64::INFINITY), 1.0);
+        assert!(jq_drem(1.0, 0.0).is_nan());
+        assert!(jq_drem(std::f64::INFINITY, 1.0).is_nan());
+    }
+
+    #[test]
+    fn string_builtins() {
+        let string = |s: &str| Ok(Value::String(s.to_string()));
+        unsafe {
+            assert_eq!(result(jq_length(wrap(r#""héllo""#))), Ok(json("5")));
+            assert_eq!(result(jq_length(wrap("-2.5"))), Ok(json("2.5")));
+            assert_eq!(result(jq_length(wrap("null"))), Ok(json("0")));
+            assert_eq!(
+                result(jq_length(wrap("true"))),
+                message("boolean (true) has no length")
+            );
+            assert_eq!(result(jq_utf8bytelength(wrap(r#""héllo""#))), Ok(json("6")));
+            assert_eq!(result(jq_ascii_downcase(wrap(r#""AbÉ""#))), string("abÉ"));
+            assert_eq!(result(jq_ascii_upcase(wrap(r#""abé""#))), string("ABé"));
+            assert_eq!(result(jq_trim(wrap(r#"" \t a b\n""#))), string("a b"));
+            assert_eq!(result(jq_ltrim(wrap(r#""  a ""#))), string("a "));
+            assert_eq!(result(jq_rtrim(wrap(r#""  a ""#))), string("  a"));
+            assert_eq!(
+                result(jq_trim(wrap("1"))),
+                message("number (1) cannot be trimmed")
+            );
+            assert_eq!(
+                result(jq_ltrimstr(wrap(r#""foobar""#), wrap(r#""foo""#))),
+                string("bar")
+            );
+            assert_eq!(
+                result(jq_rtrimstr(wrap(r#""foobar""#), wrap(r#""bar""#))),
+                string("foo")
+            );
+            assert_eq!(
+                result(jq_ltrimstr(wrap("1"), wrap(r#""foo""#))),
+                Ok(json("1"))
+            );
+            assert_eq!(
+                result(jq_startswith(wrap(r#""foobar""#), wrap(r#""foo""#))),
+                Ok(json("true"))
+            );
+            assert_eq!(
+                result(jq_endswith(wrap(r#""foobar""#), wrap(r#""foo""#))),
+                Ok(json("false"))
+            );
+            assert_eq!(
+                result(jq_startswith(wrap("1"), wrap(r#""foo""#))),
+                message("startswith() requires string inputs")
+            );
+        }
+    }
+
+    #[test]
+    fn split_and_join() {
+        unsafe {
+            assert_eq!(
+                result(jq_split(wrap(r#""a, b, c""#), wrap(r#"", ""#))),
+                Ok(json(r#"["a", "b", "c"]"#))
+            );
+            assert_eq!(
+                result(jq_split(wrap(r#""ab""#), wrap(r#""""#))),
+                Ok(json(r#"["a", "b"]"#))
+            );
+            assert_eq!(
+                result(jq_split(wrap(r#""""#), wrap(r#"",""#))),
+                Ok(json("[]"))
+            );
+            assert_eq!(
+                result(jq_split(wrap("1"), wrap(r#"",""#))),
+                message("split input and separator must be strings")
+            );
+            assert_eq!(
+                result(jq_split(wrap(r#""""#), wrap("1"))),
+                message("split input and separator must be strings")
+            );
+            assert_eq!(
+                result(jq_join(wrap(r#"["a", 1, null, true]"#), wrap(r#""-""#))),
+                Ok(json(r#""a-1--true""#))
+            );
+            assert_eq!(
+                result(jq_join(wrap("[]"), wrap(r#""-""#))),
+                Ok(json(r#""""#))
+            );
+            assert_eq!(
+                result(jq_join(wrap("1"), wrap(r#""-""#))),
+                message("Cannot iterate over number (1)")
+            );
+        }
+    }
+
+    #[test]
+    fn explode_and_implode() {
+        unsafe {
+            assert_eq!(
+                result(jq_explode(wrap(r#""aé😀""#))),
+                Ok(json("[97, 233, 128512]"))
+            );
+            assert_eq!(
+                result(jq_implode(wrap("[97, 233, 128512]"))),
+                Ok(json(r#""aé😀""#))
+            );
+            for invalid in &["[-1]", "[55296]", "[1114112]", "[1e20]"] {
+                assert_eq!(
+                    result(jq_implode(wrap(invalid))),
+                    message("Invalid codepoint literal"),
+                    "{}",
+                    invalid
+                );
+            }
+            assert_eq!(
+                result(jq_implode(at(alloc(Value::Array(vec![Value::F64(
+                    std::f64::NAN
+                )]))))),
+                message("Invalid codepoint literal")
+            );
+            assert_eq!(
+                result(jq_implode(wrap(r#"["a"]"#))),
+                message("Unicode codepoint must be numeric")
+            );
+            assert_eq!(
+                result(jq_implode(wrap(r#""a""#))),
+                message(r#"string ("a") cannot be imploded, implode input must be an array"#)
+            );
+        }
+    }
+
+    #[test]
+    fn string_conversions() {
+        let string = |s: &str| Ok(Value::String(s.to_string()));
+        unsafe {
+            assert_eq!(result(jq_tostring(wrap(r#""a""#))), string("a"));
+            assert_eq!(
+                result(jq_tostring(wrap("[1, \"a\"]"))),
+                string(r#"[1,"a"]"#)
+            );
+            assert_eq!(result(jq_tojson(wrap(r#""a""#))), string(r#""a""#));
+            assert_eq!(
+                result(jq_tostring(at(alloc(Value::F64(std::f64::INFINITY))))),
+                string("1.7976931348623157e+308")
+            );
+            assert_eq!(
+                result(jq_tojson(at(alloc(Value::F64(std::f64::NAN))))),
+                string("null")
+            );
+            assert_eq!(
+                result(jq_fromjson(wrap(r#""{\"a\": [1]}""#))),
+                Ok(json(r#"{"a": [1]}"#))
+            );
+            assert!(result(jq_fromjson(wrap(r#""{""#))).is_err());
+            assert_eq!(result(jq_tonumber(wrap(r#"" 1.5""#))), Ok(json("1.5")));
+            assert_eq!(result(jq_tonumber(wrap("3"))), Ok(json("3")));
+            assert_eq!(
+                result(jq_tonumber(wrap(r#""abc""#))),
+                message("Cannot parse 'abc' as a number")
+            );
+            assert_eq!(
+                result(jq_tonumber(wrap("[]"))),
+                message("array ([]) cannot be parsed as a number")
+            );
+        }
+    }
+
+    #[test]
+    fn indices_of_substrings_and_elements() {
+        unsafe {
+            assert_eq!(
+                result(jq_indices(wrap(r#""a,b, cd, efg""#), wrap(r#"", ""#))),
+                Ok(json("[3, 7]"))
+            );
+            assert_eq!(
+                result(jq_indices(wrap(r#""aaa""#), wrap(r#""aa""#))),
+                Ok(json("[0, 1]"))
+            );
+            assert_eq!(
+                result(jq_indices(wrap("[0, 1, 2, 1, 3, 1, 2]"), wrap("1"))),
+                Ok(json("[1, 3, 5]"))
+            );
+            assert_eq!(
+                result(jq_indices(wrap("[0, 1, 2, 1, 3, 1, 2]"), wrap("[1, 2]"))),
+                Ok(json("[1, 5]"))
+            );
+            assert_eq!(
+                result(jq_indices(wrap(r#""abc""#), wrap(r#""""#))),
+                Ok(json("null"))
+            );
+            assert_eq!(
+                result(jq_indices(wrap("null"), wrap(r#""a""#))),
+                Ok(json("null"))
+            );
         }
-    } else {
-        wrap.error = 2;
     }
-    wrap
 }